@@ -0,0 +1,162 @@
+use actix_web::{
+    body::{BodySize, MessageBody},
+    web::Bytes,
+};
+use flate2::{write::GzEncoder, Compression};
+use log::error;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+/// Chunks waiting for the client, so a slow download holds the archiver back
+/// instead of piling the archive up in memory
+const QUEUE: usize = 8;
+/// Size of the chunks handed to the response
+const CHUNK: usize = 64 * 1024;
+
+/// Sends whatever is written to it to the response in [`CHUNK`] sized pieces.
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// A gzipped tarball that is written on a blocking thread while it is sent.
+pub struct TarGz {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+}
+
+impl MessageBody for TarGz {
+    type Error = io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().rx.poll_recv(cx)
+    }
+}
+
+/// Archive `dir` (relative to the layers) under the name `name`, entries in
+/// higher layers shadowing those below them like in listings. `include` gets
+/// every path relative to the layers and leaves out those it refuses.
+///
+/// With `follow` symlinks are archived as the files and directories they
+/// point to, each directory at most once so links cannot loop. Without it
+/// they are stored as symlinks and never entered.
+pub fn tar_gz<F>(
+    layers: Vec<PathBuf>,
+    dir: PathBuf,
+    name: String,
+    follow: bool,
+    include: F,
+) -> TarGz
+where
+    F: Fn(&Path) -> bool + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(QUEUE);
+    // On the runtime's blocking pool, which caps how many threads archives can take
+    actix_web::rt::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(CHUNK),
+        };
+        if let Err(e) = write(writer, &layers, &dir, &name, follow, include) {
+            // The client going away is what stops most unfinished archives
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                error!("[ERROR] Failed to archive {}: {}", dir.display(), e);
+                // Breaks the response off, so the client cannot mistake it for complete
+                tx.blocking_send(Err(e)).ok();
+            }
+        }
+    });
+    TarGz { rx }
+}
+
+fn write<F>(
+    writer: ChannelWriter,
+    layers: &[PathBuf],
+    dir: &Path,
+    name: &str,
+    follow: bool,
+    include: F,
+) -> io::Result<()>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    builder.follow_symlinks(follow);
+    let mut seen = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(sub) = pending.pop() {
+        let sources = layers
+            .iter()
+            .map(|layer| layer.join(dir).join(&sub))
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+        if let Some(source) = sources.first() {
+            builder.append_dir(Path::new(name).join(&sub), source)?;
+        }
+        for path in sources {
+            if follow && !path.canonicalize().is_ok_and(|path| visited.insert(path)) {
+                continue;
+            }
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let relative = sub.join(entry.file_name());
+                if !seen.insert(relative.clone()) || !include(&dir.join(&relative)) {
+                    continue;
+                }
+                let path = entry.path();
+                let metadata = match if follow {
+                    path.metadata()
+                } else {
+                    path.symlink_metadata()
+                } {
+                    Ok(metadata) => metadata,
+                    // Dangling symlinks have nothing to archive
+                    Err(_) => continue,
+                };
+                if metadata.is_dir() {
+                    pending.push(relative);
+                } else if metadata.is_file() || metadata.file_type().is_symlink() {
+                    builder.append_path_with_name(&path, Path::new(name).join(&relative))?;
+                }
+            }
+        }
+    }
+    builder.into_inner()?.finish()?.flush()
+}
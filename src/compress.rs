@@ -0,0 +1,186 @@
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ResponseHead, ServiceRequest},
+    http::{
+        header::{self, AcceptEncoding, ContentEncoding, Encoding, HeaderValue},
+        StatusCode,
+    },
+    web::Bytes,
+    HttpMessage,
+};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::{
+    io::{self, Write},
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Encodings that can be applied at a chosen level, `identity` for none
+static SUPPORTED: [Encoding; 5] = [
+    Encoding::identity(),
+    Encoding::brotli(),
+    Encoding::zstd(),
+    Encoding::gzip(),
+    Encoding::deflate(),
+];
+
+/// The encoding the client prefers out of the supported ones, if it is not
+/// `identity` and the request accepts one at all.
+pub fn negotiate(req: &ServiceRequest) -> Option<ContentEncoding> {
+    let accept = req.get_header::<AcceptEncoding>()?;
+    match accept.negotiate(SUPPORTED.iter())? {
+        Encoding::Known(ContentEncoding::Identity) | Encoding::Unknown(_) => None,
+        Encoding::Known(encoding) => Some(encoding),
+    }
+}
+
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// `level` goes from 1 (fastest) to 9 (smallest) for every encoding
+    fn new(encoding: ContentEncoding, level: u32) -> Option<Self> {
+        Some(match encoding {
+            // 22 is the window size actix-http uses as well
+            ContentEncoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                vec![],
+                32 * 1024,
+                level,
+                22,
+            ))),
+            ContentEncoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(vec![], level as i32).ok()?)
+            }
+            ContentEncoding::Gzip => Encoder::Gzip(GzEncoder::new(vec![], Compression::new(level))),
+            ContentEncoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(vec![], Compression::new(level)))
+            }
+            _ => return None,
+        })
+    }
+
+    /// Compress `data`, returning whatever output is ready so far
+    fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(mem::take(output)))
+    }
+
+    /// The rest of the output, up to the end of the stream
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Brotli(encoder) => encoder.into_inner(),
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Deflate(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+/// A response body compressed at the level given to `--compress`, which the
+/// Compress middleware of actix-web has no setting for.
+pub struct Compressed {
+    body: BoxBody,
+    encoder: Option<Encoder>,
+    finished: bool,
+}
+
+impl Compressed {
+    /// Wrap `body` in `encoding`, unless it is empty or already encoded, in
+    /// which case it is passed on as is.
+    pub fn new(
+        head: &mut ResponseHead,
+        body: BoxBody,
+        encoding: ContentEncoding,
+        level: u32,
+    ) -> Self {
+        let skip = matches!(body.size(), BodySize::None | BodySize::Sized(0))
+            || head.headers().contains_key(header::CONTENT_ENCODING)
+            || head.status == StatusCode::SWITCHING_PROTOCOLS
+            || head.status == StatusCode::NO_CONTENT;
+        let encoder = if skip {
+            None
+        } else {
+            Encoder::new(encoding, level)
+        };
+        if encoder.is_some() {
+            head.headers_mut()
+                .insert(header::CONTENT_ENCODING, encoding.to_header_value());
+            head.headers_mut()
+                .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+            head.no_chunking(false);
+        }
+        Compressed {
+            body,
+            encoder,
+            finished: false,
+        }
+    }
+}
+
+impl MessageBody for Compressed {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        match self.encoder {
+            Some(_) => BodySize::Stream,
+            None => self.body.size(),
+        }
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(None);
+        }
+        loop {
+            let encoder = match this.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Pin::new(&mut this.body).poll_next(cx),
+            };
+            match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let output = encoder.write(&chunk)?;
+                    // Encoders buffer, so small chunks often produce nothing yet
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output)));
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.finished = true;
+                    let output = this.encoder.take().map(Encoder::finish).transpose()?;
+                    return Poll::Ready(output.map(Ok));
+                }
+                other => return other,
+            }
+        }
+    }
+}
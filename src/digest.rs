@@ -0,0 +1,162 @@
+use actix_web::{dev::ServiceRequest, http, HttpResponse};
+use md5::{Digest, Md5};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const REALM: &str = "srv";
+/// How long a nonce handed out in a challenge stays usable
+const NONCE_LIFETIME: Duration = Duration::from_secs(300);
+/// Upper bound on outstanding nonces, unauthenticated requests each create one
+const MAX_NONCES: usize = 4096;
+
+struct Nonce {
+    issued: Instant,
+    count: u32,
+}
+
+static NONCES: Mutex<BTreeMap<String, Nonce>> = Mutex::new(BTreeMap::new());
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+fn md5_hex(data: &str) -> String {
+    format!("{:x}", Md5::digest(data.as_bytes()))
+}
+
+/// `HA1 = MD5(username:realm:password)`, computed once at startup so the
+/// password itself is never kept around.
+pub fn ha1(username: &str, password: &str) -> String {
+    md5_hex(&format!("{}:{}:{}", username, REALM, password))
+}
+
+/// Parse the `key=value, key="value"` list of a Digest `Authorization` header.
+fn parse(header: &str) -> Option<BTreeMap<String, String>> {
+    let (scheme, mut rest) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("digest") {
+        return None;
+    }
+    let mut params = BTreeMap::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return Some(params);
+        }
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => match value.find(',') {
+                Some(end) => (value[..end].trim_end(), &value[end..]),
+                None => (value.trim_end(), ""),
+            },
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+        rest = remaining;
+    }
+}
+
+/// Verify the request against the stored credentials. On failure the error
+/// tells whether the only problem was an expired or unknown nonce.
+pub fn check(req: &ServiceRequest, username: &str, ha1: &str) -> Result<(), bool> {
+    let params = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse)
+        .ok_or(false)?;
+    let param = |key: &str| params.get(key).map(String::as_str).ok_or(false);
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map(|uri| uri.as_str())
+        .unwrap_or("/");
+    if param("username")? != username
+        || param("realm")? != REALM
+        || param("uri")? != uri
+        || param("qop")? != "auth"
+    {
+        return Err(false);
+    }
+    let nonce = param("nonce")?;
+    let nc = param("nc")?;
+    let count = u32::from_str_radix(nc, 16).map_err(|_| false)?;
+    let ha2 = md5_hex(&format!("{}:{}", req.method(), uri));
+    let expected = md5_hex(&format!(
+        "{}:{}:{}:{}:auth:{}",
+        ha1,
+        nonce,
+        nc,
+        param("cnonce")?,
+        ha2
+    ));
+    let response = param("response")?;
+    if response.len() != expected.len()
+        || response
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            != 0
+    {
+        return Err(false);
+    }
+    let mut nonces = NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    nonces.retain(|_, nonce| nonce.issued.elapsed() < NONCE_LIFETIME);
+    match nonces.get_mut(nonce) {
+        None => Err(true),
+        // A reused nonce count means the request is being replayed
+        Some(nonce) if count <= nonce.count => Err(false),
+        Some(nonce) => {
+            nonce.count = count;
+            Ok(())
+        }
+    }
+}
+
+/// A `401` carrying a fresh nonce.
+pub fn challenge(stale: bool) -> HttpResponse {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!("{}:{}", now, COUNTER.fetch_add(1, Ordering::SeqCst));
+    let nonce = format!("{:x}", sha2::Sha256::digest(seed.as_bytes()));
+    let mut nonces = NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    if nonces.len() >= MAX_NONCES {
+        nonces.retain(|_, nonce| nonce.issued.elapsed() < NONCE_LIFETIME);
+    }
+    if nonces.len() >= MAX_NONCES {
+        let oldest = nonces
+            .iter()
+            .min_by_key(|(_, nonce)| nonce.issued)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            nonces.remove(&oldest);
+        }
+    }
+    nonces.insert(
+        nonce.clone(),
+        Nonce {
+            issued: Instant::now(),
+            count: 0,
+        },
+    );
+    HttpResponse::Unauthorized()
+        .insert_header((
+            http::header::WWW_AUTHENTICATE,
+            format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm=MD5, nonce=\"{}\"{}",
+                REALM,
+                nonce,
+                if stale { ", stale=true" } else { "" }
+            ),
+        ))
+        .finish()
+}
@@ -0,0 +1,74 @@
+use log::error;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Per-directory overrides, never listed or served itself
+pub const FILE_NAME: &str = ".webconfig.toml";
+
+/// Settings a directory may override for itself and everything below it.
+/// Unset keys fall through to the parent directory, then the command line.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirConfig {
+    pub noindex: Option<bool>,
+    pub dotfiles: Option<bool>,
+    pub index: Option<Vec<String>>,
+    /// Globs relative to the directory holding the file
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+static CACHE: Mutex<Option<HashMap<PathBuf, (SystemTime, DirConfig)>>> = Mutex::new(None);
+
+/// The config file in `dir`, if there is a valid one. Parsed files are cached
+/// until they change.
+pub fn load(dir: &Path) -> Option<DirConfig> {
+    let path = dir.join(FILE_NAME);
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    if let Some(cache) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if let Some((_, config)) = cache.get(&path).filter(|(m, _)| *m == modified) {
+            return Some(config.clone());
+        }
+    }
+    let config = match toml::from_str::<DirConfig>(&fs::read_to_string(&path).ok()?) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("[ERROR] Invalid {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .get_or_insert_with(HashMap::new)
+        .insert(path, (modified, config.clone()));
+    Some(config)
+}
+
+/// The overrides in effect for `dir` (relative to the layers): the configs of
+/// its ancestors from the top down, a higher layer winning over a lower one.
+pub fn resolve(layers: &[PathBuf], dir: &Path) -> DirConfig {
+    let mut resolved = DirConfig::default();
+    let mut ancestors = dir.ancestors().collect::<Vec<_>>();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        for config in layers
+            .iter()
+            .rev()
+            .filter_map(|layer| load(&layer.join(ancestor)))
+        {
+            resolved.noindex = config.noindex.or(resolved.noindex);
+            resolved.dotfiles = config.dotfiles.or(resolved.dotfiles);
+            resolved.index = config.index.or(resolved.index);
+        }
+    }
+    resolved
+}
@@ -118,3 +118,31 @@ pub fn get_file_type(from: &std::path::Path) -> String {
     }
     .to_string()
 }
+
+/// Whether a response of this type is already compressed, so running it through
+/// gzip/brotli again would only burn CPU.
+pub fn is_compressed(mime: &mime_guess::Mime) -> bool {
+    match (mime.type_(), mime.subtype().as_str()) {
+        (mime_guess::mime::IMAGE, "svg") => false,
+        (mime_guess::mime::IMAGE, _) => true,
+        (mime_guess::mime::VIDEO, _) => true,
+        (mime_guess::mime::AUDIO, _) => true,
+        (mime_guess::mime::FONT, "woff") => true,
+        (mime_guess::mime::FONT, "woff2") => true,
+        (mime_guess::mime::APPLICATION, subtype) => matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "x-rar-compressed"
+                | "vnd.rar"
+                | "zstd"
+                | "x-compress"
+        ),
+        _ => false,
+    }
+}
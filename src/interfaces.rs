@@ -0,0 +1,20 @@
+use std::net::IpAddr;
+
+/// Addresses assigned to the network interfaces of this machine.
+pub fn addresses() -> Vec<IpAddr> {
+    if_addrs::get_if_addrs()
+        .map(|interfaces| interfaces.iter().map(|interface| interface.ip()).collect())
+        .unwrap_or_default()
+}
+
+/// Non-loopback, non-link-local IPv4 addresses, the ones other devices on the
+/// network can usually reach. The first one is treated as the primary address.
+pub fn lan_addresses() -> Vec<IpAddr> {
+    addresses()
+        .into_iter()
+        .filter(|addr| match addr {
+            IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local() && !v4.is_unspecified(),
+            IpAddr::V6(_) => false,
+        })
+        .collect()
+}
@@ -0,0 +1,129 @@
+use log::error;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Roll the log over once it would grow past this many bytes
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rolled over logs kept next to the live one (`.1` is the newest)
+const KEEP: usize = 5;
+
+pub struct LogFile {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    file: Option<File>,
+    size: u64,
+    /// Whether the last rotation failed, so a failure is only reported once
+    failed: bool,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            inner: Mutex::new(Inner {
+                file: Some(file),
+                size,
+                failed: false,
+            }),
+        })
+    }
+
+    /// Append one line, rotating first if it would not fit. The lock is held
+    /// across the rotation so concurrent lines wait instead of being dropped.
+    /// When rotating fails the line goes to the live file all the same.
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let len = line.len() as u64 + 1;
+        if inner.size > 0 && inner.size + len > MAX_SIZE {
+            match self.rotate(&mut inner) {
+                Ok(()) => inner.failed = false,
+                Err(e) => {
+                    if !inner.failed {
+                        error!("[ERROR] Failed to rotate {}: {}", self.path.display(), e);
+                        inner.failed = true;
+                    }
+                    // Tried again once another MAX_SIZE has been written
+                    inner.size = 0;
+                }
+            }
+        }
+        if inner.file.is_none() {
+            inner.file = Some(open(&self.path)?);
+        }
+        if let Some(file) = inner.file.as_mut() {
+            writeln!(file, "{}", line)?;
+        }
+        inner.size += len;
+        Ok(())
+    }
+
+    fn rotate(&self, inner: &mut Inner) -> io::Result<()> {
+        // Close the live file first, Windows refuses to rename open files
+        inner.file.take();
+        for n in (1..KEEP).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        if self.path.exists() {
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        inner.file = Some(open(&self.path)?);
+        inner.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o640);
+    }
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Fixture;
+
+    #[test]
+    fn lines_survive_a_failed_rotation() {
+        let fixture = Fixture::new(&[]);
+        let path = fixture.file("access.log", "");
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(MAX_SIZE)
+            .unwrap();
+        // The oldest log cannot be moved over a directory
+        fixture.file("access.log.4", "");
+        fixture.file("access.log.5/keep", "");
+        let log = LogFile::open(&path).unwrap();
+        log.write_line("first").unwrap();
+        log.write_line("second").unwrap();
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(contents.len() as u64, MAX_SIZE + 13);
+        assert!(contents.ends_with(b"first\nsecond\n"));
+    }
+}
@@ -2,25 +2,42 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+mod archive;
+mod compress;
+mod digest;
+mod dirconfig;
 mod filetype;
+mod interfaces;
+mod logfile;
+mod metrics;
+mod proxy;
+mod realip;
+mod sensitive;
+#[cfg(test)]
+mod tests;
+mod throttle;
+mod thumbnail;
+mod watch;
 
 use actix_web::{
-    dev::{Response, Service, ServiceRequest, ServiceResponse},
-    http, middleware, App, HttpRequest, HttpResponse, HttpServer,
+    dev::{Service, ServiceFactory, ServiceRequest, ServiceResponse},
+    http,
+    middleware::{self, ErrorHandlerResponse},
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer,
 };
 use actix_web_httpauth::{
     extractors::{basic::BasicAuth, AuthenticationError},
     headers::www_authenticate::basic::Basic,
     middleware::HttpAuthentication,
 };
-use askama_actix::TemplateToResponse;
 use clap::{arg, command, ArgAction};
 use env_logger::fmt::Color;
-use log::{error, info};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::{
     borrow::Cow,
+    collections::{HashMap, HashSet},
     env::{set_var, var},
     fs::{self, metadata, read_dir, read_to_string},
     io::{self, BufReader, Read, Write},
@@ -28,31 +45,58 @@ use std::{
     path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
+    sync::{Arc, Mutex},
 };
 use time::OffsetDateTime;
 
+/// Access log format understood by the colorizing formatter in `main`.
+const DEFAULT_LOG_FORMAT: &str = "%t^%a^%s^%D^%r";
+/// Fields of a `--log-json` record, split and serialized by the log formatter
+const JSON_LOG_FORMAT: &str = "%t^%a^%s^%D^%b^%r";
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
 #[derive(Deserialize)]
 struct Package {
     name: String,
+    id: String,
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
 }
 
+/// The parts of `cargo metadata --no-deps` the `doc` subcommand needs
 #[derive(Deserialize)]
-struct CargoToml {
-    package: Package,
+struct CargoMetadata {
+    packages: Vec<Package>,
+    /// Only reported by cargo 1.71 and later
+    #[serde(default)]
+    workspace_default_members: Vec<String>,
+    target_directory: PathBuf,
 }
 
 #[derive(Eq, Ord, PartialEq, PartialOrd, Serialize)]
 struct Dir {
     name: String,
+    /// Unix seconds, `?sort=modified` orders by this and not the display strings
+    modified_time: i64,
     modified: String,
+    modified_relative: String,
 }
 
 #[derive(Eq, Ord, PartialEq, PartialOrd, Serialize)]
 struct File {
     name: String,
+    /// Unix seconds, `?sort=modified` orders by this and not the display strings
+    modified_time: i64,
     size: u64,
     filetype: String,
     modified: String,
+    modified_relative: String,
+    thumbnail: bool,
 }
 
 #[derive(askama_actix::Template)]
@@ -64,33 +108,322 @@ struct IndexContext {
     paths: Vec<String>,
     dirs: Vec<Dir>,
     files: Vec<File>,
+    theme: String,
+    /// `absolute` or `relative`, how modified times are shown
+    time_format: String,
+    search: String,
+    total_dirs: usize,
+    total_files: usize,
+    /// Bytes in the listed files of every page, subdirectories not included
+    total_size: u64,
+    page: usize,
+    pages: usize,
+    /// Query string carried over to the page links, ends with `&` when not empty
+    page_query: String,
+}
+
+/// Served at `/favicon.ico` when the root has none
+const FAVICON: &[u8] = include_bytes!("../templates/favicon.ico");
+
+/// How long ago `time` was, e.g. "3 minutes ago", "yesterday" or "2 weeks ago".
+fn relative_time(time: std::time::SystemTime) -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(time)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let ago = |count: u64, unit: &str| match count {
+        1 => format!("a{} {} ago", if unit == "hour" { "n" } else { "" }, unit),
+        count => format!("{} {}s ago", count, unit),
+    };
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => ago(secs / 60, "minute"),
+        3600..=86399 => ago(secs / 3600, "hour"),
+        86400..=172799 => "yesterday".to_string(),
+        172800..=604799 => ago(secs / 86400, "day"),
+        604800..=2591999 => ago(secs / 604800, "week"),
+        2592000..=31535999 => ago(secs / 2592000, "month"),
+        _ => ago(secs / 31536000, "year"),
+    }
+}
+
+/// Largest `per_page` a client may ask for
+const MAX_PAGE_SIZE: usize = 10000;
+
+/// Sort the entries, by name or oldest first with `?sort=modified`, and keep
+/// only the page requested by `?page=N&per_page=M`. Directories come before
+/// files, so the order is the same on every page.
+fn paginate(req: &HttpRequest, context: &mut IndexContext) {
+    let by_modified = req
+        .query_string()
+        .split('&')
+        .any(|pair| pair == "sort=modified");
+    if by_modified {
+        context
+            .dirs
+            .sort_by(|a, b| (a.modified_time, &a.name).cmp(&(b.modified_time, &b.name)));
+        context
+            .files
+            .sort_by(|a, b| (a.modified_time, &a.name).cmp(&(b.modified_time, &b.name)));
+    } else {
+        context.dirs.sort();
+        context.files.sort();
+    }
+    context.total_dirs = context.dirs.len();
+    context.total_files = context.files.len();
+    context.total_size = context.files.iter().map(|file| file.size).sum();
+    let param = |key: &str| {
+        req.query_string()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+            .and_then(|value| value.parse::<usize>().ok())
+    };
+    let default_size = var("PAGE_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<usize>().ok())
+        .unwrap_or(0);
+    // Only --page-size can turn paging off, a client asking for 0 gets the default
+    let requested = param("per_page").filter(|size| *size > 0);
+    let per_page = requested.unwrap_or(default_size).min(MAX_PAGE_SIZE);
+    let total = context.total_dirs + context.total_files;
+    if per_page == 0 || total <= per_page {
+        return;
+    }
+    context.pages = total.div_ceil(per_page);
+    context.page = param("page").unwrap_or(1).clamp(1, context.pages);
+    let start = (context.page - 1) * per_page;
+    let end = start + per_page;
+    context.files = context
+        .files
+        .drain(
+            start
+                .saturating_sub(context.total_dirs)
+                .min(context.total_files)
+                ..end
+                    .saturating_sub(context.total_dirs)
+                    .min(context.total_files),
+        )
+        .collect();
+    context.dirs = context
+        .dirs
+        .drain(start.min(context.total_dirs)..end.min(context.total_dirs))
+        .collect();
+    if !context.search.is_empty() {
+        context.page_query += &format!("search={}&", urlencoding::encode(&context.search));
+    }
+    if let Some(per_page) = requested {
+        context.page_query += &format!("per_page={}&", per_page);
+    }
+    if by_modified {
+        context.page_query += "sort=modified&";
+    }
+}
+
+/// Metadata of the listed entry `path`, that of the symlink itself when
+/// symlinks are not followed. Errors are logged and the entry left out.
+fn entry_metadata(path: &fs::DirEntry, no_follow: bool) -> Option<fs::Metadata> {
+    let is_symlink = path.file_type().is_ok_and(|t| t.is_symlink());
+    // Without following, a symlink is listed as itself rather than its target
+    let metadata = if no_follow && is_symlink {
+        fs::symlink_metadata(path.path())
+    } else {
+        path.metadata()
+    };
+    match metadata {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            error!(target: "read_dir", "[ERROR] Read metadata error: {}", e.to_string());
+            None
+        }
+    }
+}
+
+/// Add the entry at `path` with `metadata` to the listing, shown as `name`.
+fn push_entry(context: &mut IndexContext, name: String, path: &Path, metadata: &fs::Metadata) {
+    let (modified_time, modified, modified_relative) = match metadata.modified() {
+        Ok(time) => (
+            OffsetDateTime::from(time).unix_timestamp(),
+            OffsetDateTime::from(time)
+                .format(time::macros::format_description!(
+                    "[year]/[month]/[day] [hour]:[minute]:[second]"
+                ))
+                .unwrap_or_else(|_| "".to_string()),
+            relative_time(time),
+        ),
+        Err(e) => {
+            error!(target: "read_dir", "[ERROR] Read modified time error: {}", e.to_string());
+            return;
+        }
+    };
+    if metadata.is_dir() {
+        context.dirs.push(Dir {
+            name,
+            modified,
+            modified_relative,
+            modified_time,
+        });
+    } else if metadata.is_symlink() {
+        context.files.push(File {
+            name,
+            size: metadata.len(),
+            filetype: "symlink".to_string(),
+            modified,
+            modified_relative,
+            modified_time,
+            thumbnail: false,
+        });
+    } else if metadata.is_file() {
+        let size = metadata.len();
+        let thumbnail = var("THUMBNAILS").unwrap_or_else(|_| "false".to_string()) == "true"
+            && size <= thumbnail::MAX_FILE_SIZE
+            && thumbnail::is_supported(path);
+        context.files.push(File {
+            name,
+            size,
+            filetype: filetype::get_file_type(path),
+            modified,
+            modified_relative,
+            modified_time,
+            thumbnail,
+        });
+    }
+}
+
+/// Deepest directory level a listing search descends to
+const SEARCH_MAX_DEPTH: usize = 16;
+/// Searches stop after this many matches
+const SEARCH_MAX_RESULTS: usize = 500;
+
+/// Walk `relative` (a directory under ROOT) in every layer and collect the
+/// entries whose name contains `term`, case-insensitively. Entry names are
+/// paths relative to `relative` so the listing can link to them directly.
+/// Only what the listings of the walked directories show is found.
+fn search(relative: &Path, term: &str, context: &mut IndexContext) {
+    let term = term.to_lowercase();
+    let no_follow = var("NO_FOLLOW_SYMLINKS").unwrap_or_else(|_| "false".to_string()) == "true";
+    let mut seen = HashSet::new();
+    let mut pending = vec![(PathBuf::new(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        let show_dot_files = dirconfig::resolve(&layers(), &relative.join(&dir))
+            .dotfiles
+            .unwrap_or_else(|| var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true");
+        for layer in layers() {
+            let paths = match read_dir(layer.join(relative).join(&dir)) {
+                Ok(paths) => paths,
+                Err(_) => continue,
+            };
+            for path in paths.flatten() {
+                if context.dirs.len() + context.files.len() >= SEARCH_MAX_RESULTS {
+                    return;
+                }
+                let name = match path.file_name().to_str() {
+                    Some(str) => str.to_string(),
+                    None => continue,
+                };
+                let entry = dir.join(&name);
+                // Entries in higher layers shadow those below them
+                if !seen.insert(entry.clone()) || !is_listed(&relative.join(&entry), show_dot_files)
+                {
+                    continue;
+                }
+                let metadata = match entry_metadata(&path, no_follow) {
+                    Some(metadata) => metadata,
+                    None => continue,
+                };
+                // Symlinked directories are listed but not followed, they may loop
+                let is_symlink = path.file_type().is_ok_and(|t| t.is_symlink());
+                if metadata.is_dir()
+                    && !is_symlink
+                    && depth < SEARCH_MAX_DEPTH
+                    && !is_noindex(&relative.join(&entry))
+                {
+                    pending.push((entry.clone(), depth + 1));
+                }
+                if !name.to_lowercase().contains(&term) {
+                    continue;
+                }
+                let name = entry
+                    .iter()
+                    .map(|part| part.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                push_entry(context, name, &path.path(), &metadata);
+            }
+        }
+    }
 }
 
 fn render_index(
     dir: &actix_files::Directory,
     req: &HttpRequest,
 ) -> Result<ServiceResponse, io::Error> {
-    let mut index = dir.path.clone();
-    index.push("index.html");
-    if index.exists() && index.is_file() {
+    let relative = dir
+        .path
+        .strip_prefix(&dir.base)
+        .unwrap_or_else(|_| Path::new(""));
+    let config = dirconfig::resolve(&layers(), relative);
+    let mut layers = layers()
+        .iter()
+        .map(|layer| layer.join(relative))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    if layers.is_empty() {
+        layers.push(dir.path.clone());
+    }
+    let index = config
+        .index
+        .unwrap_or_else(index_names)
+        .into_iter()
+        .filter(|name| !is_excluded(&relative.join(name)))
+        .find_map(|name| {
+            layers
+                .iter()
+                .map(|layer| layer.join(&name))
+                .find(|index| index.is_file())
+        });
+    if let Some(index) = index {
+        // Answered from the original request, so Range and If-Range still get a 206
         let res = actix_files::NamedFile::open(index)?
             .set_content_type(mime_guess::mime::TEXT_HTML_UTF_8)
             .into_response(req);
         return Ok(ServiceResponse::new(req.to_owned(), res));
     }
-    if var("NOINDEX").unwrap_or_else(|_| "false".to_string()) == "true" {
+    let noindex = config
+        .noindex
+        .unwrap_or_else(|| var("NOINDEX").unwrap_or_else(|_| "false".to_string()) == "true");
+    if noindex {
         return Ok(ServiceResponse::new(
             req.to_owned(),
             HttpResponse::NotFound().body(""),
         ));
     }
-    let show_dot_files = var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true";
+    // HEAD only learns that there is a page: reading the directory, searching
+    // and rendering just to count the bytes is left to GET, so no Content-Length
+    if req.method() == http::Method::HEAD {
+        let res = HttpResponse::Ok()
+            .content_type(mime_guess::mime::TEXT_HTML_UTF_8)
+            .body(actix_web::body::None::new());
+        return Ok(ServiceResponse::new(req.to_owned(), res));
+    }
+    let show_dot_files = config
+        .dotfiles
+        .unwrap_or_else(|| var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true");
+    let no_follow = var("NO_FOLLOW_SYMLINKS").unwrap_or_else(|_| "false".to_string()) == "true";
     let mut context = IndexContext {
         title: "".to_string(),
         readme: "".to_string(),
         paths: vec![],
         dirs: vec![],
         files: vec![],
+        theme: var("THEME").unwrap_or_else(|_| "auto".to_string()),
+        time_format: var("TIME_FORMAT").unwrap_or_else(|_| "absolute".to_string()),
+        search: "".to_string(),
+        total_dirs: 0,
+        total_files: 0,
+        total_size: 0,
+        page: 1,
+        pages: 1,
+        page_query: "".to_string(),
     };
     for path in req.path().split('/') {
         if path.is_empty() {
@@ -100,64 +433,60 @@ fn render_index(
         let path = path.into_owned();
         context.paths.push(path);
     }
+    let term = req
+        .query_string()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("search="))
+        .map(|term| urlencoding::decode(&term.replace('+', " ")).map(Cow::into_owned))
+        .and_then(Result::ok)
+        .filter(|term| !term.trim().is_empty());
+    if let Some(term) = term {
+        search(relative, term.trim(), &mut context);
+        context.search = term;
+        context.title = context.paths.last().unwrap_or(&"/".to_string()).to_string();
+        paginate(req, &mut context);
+        return Ok(listing_response(req, &context));
+    }
     let mut readme_str = "".to_string();
-    match read_dir(&dir.path) {
-        Err(e) => {
-            error!(target: "read_dir", "[ERROR] Read dir error: {}", e.to_string());
-        }
-        Ok(paths) => {
-            for path in paths {
-                let path = match path {
-                    Ok(path) => path,
-                    Err(e) => {
-                        error!(target: "read_dir", "[ERROR] Read path error: {}", e.to_string());
-                        continue;
-                    }
-                };
-                let name = match path.file_name().to_str() {
-                    Some(str) => str.to_string(),
-                    None => {
-                        error!(target: "read_dir", "[ERROR] Read filename error");
-                        continue;
-                    }
-                };
-                if !show_dot_files && name.starts_with('.') {
+    let mut seen = HashSet::new();
+    for layer in &layers {
+        let paths = match read_dir(layer) {
+            Ok(paths) => paths,
+            Err(e) => {
+                error!(target: "read_dir", "[ERROR] Read dir error: {}", e.to_string());
+                continue;
+            }
+        };
+        for path in paths {
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    error!(target: "read_dir", "[ERROR] Read path error: {}", e.to_string());
                     continue;
                 }
-                let metadata = match path.metadata() {
-                    Ok(data) => data,
-                    Err(e) => {
-                        error!(target: "read_dir", "[ERROR] Read metadata error: {}", e.to_string());
-                        continue;
-                    }
-                };
-                let modified = match metadata.modified() {
-                    Ok(time) => OffsetDateTime::from(time)
-                        .format(time::macros::format_description!(
-                            "[year]/[month]/[day] [hour]:[minute]:[second]"
-                        ))
-                        .unwrap_or_else(|_| "".to_string()),
-                    Err(e) => {
-                        error!(target: "read_dir", "[ERROR] Read modified time error: {}", e.to_string());
-                        continue;
-                    }
-                };
-                if metadata.is_dir() {
-                    context.dirs.push(Dir { name, modified });
-                } else if metadata.is_file() {
-                    let size = metadata.len();
-                    let filetype = filetype::get_file_type(&path.path());
-                    context.files.push(File {
-                        name,
-                        size,
-                        filetype,
-                        modified,
-                    });
-                    if path.file_name().to_ascii_lowercase() == "readme.md" {
-                        readme_str = read_to_string(path.path()).unwrap_or_else(|_| "".to_string());
-                    }
+            };
+            let name = match path.file_name().to_str() {
+                Some(str) => str.to_string(),
+                None => {
+                    error!(target: "read_dir", "[ERROR] Read filename error");
+                    continue;
                 }
+            };
+            // Entries in higher layers shadow those below them
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            if !is_listed(&relative.join(&name), show_dot_files) {
+                continue;
+            }
+            let metadata = match entry_metadata(&path, no_follow) {
+                Some(metadata) => metadata,
+                None => continue,
+            };
+            if metadata.is_file() && name.to_ascii_lowercase() == "readme.md" {
+                readme_str = read_to_string(path.path()).unwrap_or_else(|_| "".to_string());
             }
+            push_entry(&mut context, name, &path.path(), &metadata);
         }
     }
     if var("NOREADME").unwrap_or_else(|_| "false".to_string()) != "true" {
@@ -195,9 +524,609 @@ fn render_index(
         );
     }
     context.title = context.paths.last().unwrap_or(&"/".to_string()).to_string();
-    context.dirs.sort();
-    context.files.sort();
-    Ok(ServiceResponse::new(req.to_owned(), context.to_response()))
+    paginate(req, &mut context);
+    Ok(listing_response(req, &context))
+}
+
+/// Listing template loaded from `--template`, the built-in one is used without it
+static TEMPLATE: std::sync::OnceLock<tera::Tera> = std::sync::OnceLock::new();
+
+/// Parse the `--template` file, keeping the built-in template when it is invalid.
+fn load_template(path: &Path) {
+    let mut tera = tera::Tera::default();
+    let loaded = read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            tera.add_raw_template("index.html", &contents)
+                .map_err(|e| format!("{:?}", e))
+        });
+    match loaded {
+        Ok(()) => {
+            TEMPLATE.set(tera).ok();
+        }
+        Err(e) => error!(
+            "[ERROR] Failed to load template {}, using the built-in one: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn listing_response(req: &HttpRequest, context: &IndexContext) -> ServiceResponse {
+    let html = match TEMPLATE.get() {
+        Some(tera) => tera::Context::from_serialize(context)
+            .and_then(|context| tera.render("index.html", &context))
+            .map_err(|e| error!("[ERROR] Template error: {:?}", e))
+            .ok(),
+        None => askama_actix::Template::render(context).ok(),
+    };
+    let res = match html {
+        Some(html) => HttpResponse::Ok()
+            .content_type(mime_guess::mime::TEXT_HTML_UTF_8)
+            .body(html),
+        None => HttpResponse::InternalServerError().finish(),
+    };
+    ServiceResponse::new(req.to_owned(), res)
+}
+
+fn render_error<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let accept_json = res
+        .request()
+        .headers()
+        .get(http::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false);
+    // Upstream errors are the upstream's to describe
+    if !accept_json || proxy::is_proxied(res.request().path()) {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+    let status = res.status();
+    let body = serde_json::json!({
+        "error": status.canonical_reason().unwrap_or("Unknown Error"),
+        "status": status.as_u16(),
+    })
+    .to_string();
+    let (req, mut res) = res.into_parts();
+    res.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static("application/json"),
+    );
+    let res = ServiceResponse::new(req, res.set_body(body).map_into_boxed_body());
+    Ok(ErrorHandlerResponse::Response(res.map_into_right_body()))
+}
+
+/// URL prefix of ACME HTTP-01 challenges, served even when dotfiles are not.
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge";
+
+/// Whether the unescaped URL `path` goes through a dotfile that is neither an
+/// ACME challenge nor allowed by `--show-dotfile`.
+fn is_hidden_dotfile(path: &str) -> bool {
+    let acme = path
+        .strip_prefix(ACME_CHALLENGE_PATH)
+        .is_some_and(|rest| rest.starts_with('/'));
+    let mut prefix = PathBuf::new();
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .any(|segment| {
+            prefix.push(segment);
+            // certbot's webroot mode needs its challenges reachable with dotfiles hidden
+            segment.starts_with('.')
+                && !(acme && prefix == Path::new(".well-known"))
+                && !matches_globs("SHOW_DOTFILE", &prefix)
+        })
+}
+
+/// Directories files are served from, highest priority first: every
+/// `--overlay` in order, then ROOT.
+fn layers() -> Vec<PathBuf> {
+    let overlays = var("OVERLAY").unwrap_or_default();
+    let mut layers = overlays.lines().map(PathBuf::from).collect::<Vec<_>>();
+    layers.push(PathBuf::from(
+        var("ROOT").unwrap_or_else(|_| ".".to_string()),
+    ));
+    layers
+}
+
+/// Whether `relative` is made of plain names only, so joined to one of the
+/// [`layers`] it cannot reach outside of it through `..` or a root.
+fn is_plain(relative: &Path) -> bool {
+    relative
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+/// File names served in place of a directory listing, first match wins: every
+/// `--index` in order, `index.html` by default.
+fn index_names() -> Vec<String> {
+    let names = var("INDEX").unwrap_or_default();
+    if names.is_empty() {
+        return vec!["index.html".to_string()];
+    }
+    names.lines().map(str::to_string).collect()
+}
+
+/// Whether serving `relative` would go through a symlink that is not allowed:
+/// any symlink with `--no-follow-symlinks`, otherwise one whose target lies
+/// outside the layer the path was found in.
+fn through_forbidden_symlink(relative: &Path) -> bool {
+    let no_follow = var("NO_FOLLOW_SYMLINKS").unwrap_or_else(|_| "false".to_string()) == "true";
+    // The first layer that has the path is the one that serves it
+    let layer = match layers()
+        .into_iter()
+        .find(|layer| layer.join(relative).exists())
+    {
+        Some(layer) => layer,
+        None => return false,
+    };
+    if no_follow {
+        let mut path = layer;
+        return relative.components().any(|component| {
+            path.push(component);
+            path.symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        });
+    }
+    match (layer.canonicalize(), layer.join(relative).canonicalize()) {
+        (Ok(root), Ok(target)) => !target.starts_with(root),
+        _ => false,
+    }
+}
+
+/// Whether listings of `dir` (relative to ROOT) are turned off, by
+/// `--noindex` or a `.webconfig.toml`.
+fn is_noindex(dir: &Path) -> bool {
+    dirconfig::resolve(&layers(), dir)
+        .noindex
+        .unwrap_or_else(|| var("NOINDEX").unwrap_or_else(|_| "false".to_string()) == "true")
+}
+
+/// Whether the entry `path` (relative to ROOT) shows up in the listing of its
+/// directory, given whether that one shows `dotfiles`.
+fn is_listed(path: &Path, dotfiles: bool) -> bool {
+    let dotfile = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    (dotfiles || !dotfile || matches_globs("SHOW_DOTFILE", path)) && !is_excluded(path)
+}
+
+/// Whether `path` (relative to ROOT) goes into a `?download=tar.gz` archive:
+/// what a listing shows, minus symlinks leading out of the layers and the
+/// directories that may not be listed.
+fn is_archived(path: &Path, no_follow: bool) -> bool {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let dotfiles = dirconfig::resolve(&layers(), dir)
+        .dotfiles
+        .unwrap_or_else(|| var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true");
+    if !is_listed(path, dotfiles) {
+        return false;
+    }
+    let is_dir = layers().iter().any(|layer| layer.join(path).is_dir());
+    if is_dir && is_noindex(path) {
+        return false;
+    }
+    // Without following, symlinks are stored as links and never read through
+    no_follow || !through_forbidden_symlink(path)
+}
+
+/// Content encodings the Compress middleware can apply
+const COMPRESS_ENCODINGS: [&str; 4] = ["br", "gzip", "deflate", "zstd"];
+
+/// Drop the encodings left out of `--encodings` from an `Accept-Encoding`
+/// value, spelling out `*` as the allowed ones. `identity` is always kept.
+fn filter_encodings(accept: &str) -> String {
+    let allowed = match var("ENCODINGS") {
+        Ok(encodings) => encodings,
+        Err(_) => return accept.to_string(),
+    };
+    let allowed = allowed.split(',').collect::<Vec<_>>();
+    let mut filtered = vec![];
+    for item in accept.split(',') {
+        let (name, params) = item.split_once(';').unwrap_or((item, ""));
+        let name = name.trim().to_ascii_lowercase();
+        let params = if params.is_empty() {
+            String::new()
+        } else {
+            format!(";{}", params)
+        };
+        if name == "*" {
+            filtered.extend(allowed.iter().map(|name| format!("{}{}", name, params)));
+        } else if name == "identity" || allowed.contains(&name.as_str()) {
+            filtered.push(format!("{}{}", name, params));
+        }
+    }
+    filtered.join(", ")
+}
+
+/// [`globs_match`] with the patterns stored in the `key` env var, for `path`
+/// relative to ROOT.
+fn matches_globs(key: &str, path: &Path) -> bool {
+    globs_match(&var(key).unwrap_or_default(), path)
+}
+
+/// Compiled glob sets by their newline separated patterns, which come from
+/// the command line and `.webconfig.toml` files and hardly ever change
+static GLOB_SETS: Mutex<Option<HashMap<String, Arc<globset::GlobSet>>>> = Mutex::new(None);
+
+/// Whether `path` or one of its parents matches one of the newline separated
+/// `patterns`, either as a whole or by file name.
+fn globs_match(patterns: &str, path: &Path) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+    let set = {
+        let mut sets = GLOB_SETS.lock().unwrap_or_else(|e| e.into_inner());
+        let sets = sets.get_or_insert_with(HashMap::new);
+        match sets.get(patterns) {
+            Some(set) => set.clone(),
+            None => {
+                let mut builder = globset::GlobSetBuilder::new();
+                for pattern in patterns.lines() {
+                    if let Ok(glob) = globset::Glob::new(pattern) {
+                        builder.add(glob);
+                    }
+                }
+                let set = Arc::new(
+                    builder
+                        .build()
+                        .unwrap_or_else(|_| globset::GlobSet::empty()),
+                );
+                sets.insert(patterns.to_string(), set.clone());
+                set
+            }
+        }
+    };
+    path.ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .any(|path| set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name)))
+}
+
+/// Whether `path` (relative to ROOT) is hidden by `--exclude`, by the
+/// `exclude` of a `.webconfig.toml` above it, or is such a file itself.
+fn is_excluded(path: &Path) -> bool {
+    if path
+        .file_name()
+        .is_some_and(|name| name == dirconfig::FILE_NAME)
+    {
+        return true;
+    }
+    if matches_globs("EXCLUDE", path) {
+        return true;
+    }
+    let layers = layers();
+    path.ancestors().skip(1).any(|dir| {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        layers
+            .iter()
+            .filter_map(|layer| dirconfig::load(&layer.join(dir)))
+            .any(|config| globs_match(&config.exclude.join("\n"), relative))
+    })
+}
+
+/// Where to redirect `path` with `--normalize-path`: duplicate slashes are
+/// collapsed and directories get a trailing slash, files are left alone.
+fn normalized_path(path: &str) -> Option<String> {
+    if var("NORMALIZE_PATH").unwrap_or_else(|_| "false".to_string()) != "true" {
+        return None;
+    }
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for c in path.chars() {
+        if c != '/' || !normalized.ends_with('/') {
+            normalized.push(c);
+        }
+    }
+    let decoded = urlencoding::decode(&normalized).ok()?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    let is_dir = !normalized.ends_with('/')
+        && is_plain(relative)
+        && layers().iter().any(|layer| layer.join(relative).is_dir());
+    if is_dir {
+        normalized.push('/');
+    }
+    (normalized != path).then_some(normalized)
+}
+
+/// Resolve `/about` to `about.html` or one of the `about/` index files under `root`.
+fn resolve_clean_url(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = urlencoding::decode(path).ok()?;
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !is_plain(relative) {
+        return None;
+    }
+    let name = relative.file_name()?.to_string_lossy().into_owned();
+    let mut candidates = index_names()
+        .iter()
+        .map(|index| root.join(relative).join(index))
+        .collect::<Vec<_>>();
+    if !path.ends_with('/') {
+        candidates.insert(0, root.join(relative).with_file_name(name + ".html"));
+    }
+    candidates
+        .into_iter()
+        .find(|path| path.is_file() && !is_excluded(path.strip_prefix(root).unwrap_or(path)))
+}
+
+/// Whether a log message is about the client going away mid-response: a
+/// broken pipe, reset or aborted connection, in either the `Display` or the
+/// `Debug` form of the I/O error.
+fn is_disconnect(message: &str) -> bool {
+    let os_error = message
+        .split("os error ")
+        .nth(1)
+        .and_then(|code| code.split(|c: char| !c.is_ascii_digit()).next())
+        .and_then(|code| code.parse::<i32>().ok())
+        .map(|code| io::Error::from_raw_os_error(code).kind());
+    if let Some(kind) = os_error {
+        return matches!(
+            kind,
+            io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        );
+    }
+    let message = message.to_ascii_lowercase().replace([' ', '_'], "");
+    ["brokenpipe", "connectionreset", "connectionabort"]
+        .iter()
+        .any(|kind| message.contains(kind))
+}
+
+/// Log the settings read back from the env vars `main` stored them in, without
+/// any passwords.
+fn log_config(tls: bool) {
+    let flag = |key: &str| var(key).unwrap_or_else(|_| "false".to_string()) == "true";
+    let on_off = |on: bool| if on { "enabled" } else { "disabled" };
+    let auth = if flag("ENABLE_AUTH") {
+        let users = var("AUTH_USERS").unwrap_or_default();
+        let users = users.lines().filter_map(|user| user.split(':').next());
+        format!("enabled (user={})", users.collect::<Vec<_>>().join(","))
+    } else if flag("ENABLE_AUTH_DIGEST") {
+        format!(
+            "enabled, digest (user={})",
+            var("AUTH_DIGEST_USERNAME").unwrap_or_default()
+        )
+    } else {
+        "disabled".to_string()
+    };
+    let cors = if flag("ENABLE_CORS") {
+        var("CORS").unwrap_or_else(|_| "*".to_string())
+    } else {
+        "disabled".to_string()
+    };
+    let settings = [
+        (
+            "root",
+            var("SINGLE_FILE").unwrap_or_else(|_| var("ROOT").unwrap_or_else(|_| ".".to_string())),
+        ),
+        (
+            "address",
+            var("LISTEN_ADDRESS")
+                .unwrap_or_else(|_| "0.0.0.0:8000".to_string())
+                .replace('\n', ", "),
+        ),
+        ("auth", auth),
+        ("cors", cors),
+        ("tls", on_off(tls).to_string()),
+        ("spa", on_off(flag("SPA")).to_string()),
+        ("dotfiles", on_off(flag("DOTFILES")).to_string()),
+        ("nocache", on_off(flag("NOCACHE")).to_string()),
+        (
+            "keep-alive",
+            match var("KEEP_ALIVE").unwrap_or_default().as_str() {
+                "0" => "disabled".to_string(),
+                secs => format!("{}s", secs),
+            },
+        ),
+        (
+            "client timeout",
+            match var("CLIENT_TIMEOUT").unwrap_or_default().as_str() {
+                "0" => "disabled".to_string(),
+                secs => format!("{}s", secs),
+            },
+        ),
+    ];
+    for (name, value) in settings {
+        info!("[INFO] {}: {}", name, value);
+    }
+}
+
+/// Block until a TCP connection to `addr` succeeds, for at most a few seconds.
+/// Only the socket is probed, so this works the same with TLS.
+fn wait_until_listening(addr: &str) -> bool {
+    let mut addr = match addr.parse::<std::net::SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => return false,
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+        });
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while std::time::Instant::now() < deadline {
+        let timeout = std::time::Duration::from_millis(200);
+        if std::net::TcpStream::connect_timeout(&addr, timeout).is_ok() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    false
+}
+
+/// Run `cargo metadata` for the workspace in the current directory.
+fn cargo_metadata() -> Result<CargoMetadata, String> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Cargo Error: {} (is cargo installed and on PATH?)", e))?;
+    if !output.status.success() {
+        return Err("Cargo Error: cargo metadata failed".to_string());
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("Cargo Error: {}", e))
+}
+
+/// The package to document: the one asked for with `--package`, the one in
+/// the current directory, or the only (default) member of the workspace.
+fn doc_package<'a>(
+    metadata: &'a CargoMetadata,
+    requested: Option<&String>,
+) -> Result<&'a Package, String> {
+    if let Some(name) = requested {
+        return metadata
+            .packages
+            .iter()
+            .find(|package| &package.name == name)
+            .ok_or_else(|| format!("No package named {} in this workspace", name));
+    }
+    let manifest = Path::new("./Cargo.toml").canonicalize().ok();
+    let package = metadata
+        .packages
+        .iter()
+        .find(|package| Some(&package.manifest_path) == manifest.as_ref());
+    if let Some(package) = package {
+        return Ok(package);
+    }
+    let defaults = metadata
+        .packages
+        .iter()
+        .filter(|package| metadata.workspace_default_members.contains(&package.id))
+        .collect::<Vec<_>>();
+    match (defaults.as_slice(), metadata.packages.as_slice()) {
+        ([package], _) => Ok(*package),
+        ([], [package]) => Ok(package),
+        _ => Err(format!(
+            "This is a workspace, pick the package to document with --package: {}",
+            metadata
+                .packages
+                .iter()
+                .map(|package| package.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Folder rustdoc writes `package` to: named after its library target, or
+/// its first binary, with `-` turned into `_`.
+fn doc_dir_name(package: &Package) -> String {
+    let is_lib = |target: &&Target| {
+        target
+            .kind
+            .iter()
+            .any(|kind| kind.ends_with("lib") || kind == "proc-macro")
+    };
+    let target = package.targets.iter().find(is_lib).or_else(|| {
+        package
+            .targets
+            .iter()
+            .find(|target| target.kind.iter().any(|kind| kind == "bin"))
+    });
+    target
+        .map_or(package.name.as_str(), |target| target.name.as_str())
+        .replace('-', "_")
+}
+
+fn generate_doc() -> bool {
+    let mut cargo = Command::new("cargo");
+    cargo.arg("doc");
+    if let Ok(package) = var("DOC_PACKAGE") {
+        cargo.args(["--package", &package]);
+    }
+    match cargo
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+    {
+        Ok(status) => {
+            if !status.success() {
+                match status.code() {
+                    Some(code) => error!("[ERROR] Cargo exited with status code: {code}"),
+                    None => error!("[ERROR] Cargo terminated by signal"),
+                }
+                return false;
+            }
+        }
+        Err(e) => {
+            error!("[ERROR] Cargo Error: {}", e.to_string());
+            return false;
+        }
+    }
+    true
+}
+
+/// Check `status` against a `--log-status` spec: a comma separated list of
+/// exact codes (`404`), classes (`5xx`) or comparisons (`>=400`, `<300`).
+fn status_matches(spec: &str, status: u16) -> Result<bool, String> {
+    let mut matched = false;
+    for item in spec.split(',').map(str::trim) {
+        let (op, value) = [">=", "<=", ">", "<", "="]
+            .iter()
+            .find_map(|op| item.strip_prefix(op).map(|value| (*op, value.trim())))
+            .unwrap_or(("=", item));
+        let (status, value) = if value.len() == 3 && value.ends_with("xx") {
+            (status / 100, value[..1].parse::<u16>())
+        } else {
+            (status, value.parse::<u16>())
+        };
+        let value = value.map_err(|_| format!("Invalid status spec: {}", item))?;
+        matched |= match op {
+            ">=" => status >= value,
+            "<=" => status <= value,
+            ">" => status > value,
+            "<" => status < value,
+            _ => status == value,
+        };
+    }
+    Ok(matched)
+}
+
+/// Expand a leading `~` and `$VAR`/`${VAR}` (plus `%VAR%` on Windows) the way
+/// a shell would, for paths that were quoted or come from a config. `~user` is
+/// left alone, an undefined variable is an error.
+fn expand_path(path: &str) -> Result<String, String> {
+    let lookup = |name: &str| var(name).map_err(|_| format!("Undefined variable: {}", name));
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let home = if cfg!(windows) {
+            lookup("USERPROFILE")
+        } else {
+            lookup("HOME")
+        };
+        expanded.push_str(&home?);
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find(|c: char| c == '$' || (cfg!(windows) && c == '%')) {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, len) = if rest[i..].starts_with('%') {
+            match after.find('%') {
+                Some(end) => (&after[..end], end + 1),
+                None => ("", 0),
+            }
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => return Err(format!("Unterminated variable in {}", path)),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        if name.is_empty() {
+            // A lone `$` or `%` is taken literally
+            expanded.push_str(&rest[i..i + 1]);
+        } else {
+            expanded.push_str(&lookup(name)?);
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
 #[inline]
@@ -208,56 +1137,690 @@ fn display_path(path: &Path) -> String {
     } else {
         root
     }
-}
-
-#[inline]
-fn hash(from: &str) -> String {
-    let mut hasher = sha2::Sha512::new();
-    hasher.update(from);
-    format!("{:?}", hasher.finalize())
-}
-
-#[inline]
-async fn validator(
-    req: ServiceRequest,
-    auth: BasicAuth,
-) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
-    if auth.user_id()
-        == var("AUTH_USERNAME")
-            .unwrap_or_else(|_| "".to_string())
-            .as_str()
-        && hash(auth.password().unwrap_or(&Cow::from("")))
-            == var("AUTH_PASSWORD")
-                .unwrap_or_else(|_| "".to_string())
-                .as_str()
-    {
-        return Ok(req);
+}
+
+/// Load the first private key found in a PEM file, trying PKCS#8, then
+/// PKCS#1 (RSA), then SEC1 (EC) encodings.
+fn load_private_key(path: &Path) -> io::Result<Option<rustls::PrivateKey>> {
+    let mut contents = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut contents)?;
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut contents.as_slice())?;
+    if keys.is_empty() {
+        keys = rustls_pemfile::rsa_private_keys(&mut contents.as_slice())?;
+    }
+    if keys.is_empty() {
+        keys = rustls_pemfile::ec_private_keys(&mut contents.as_slice())?;
+    }
+    Ok(keys.into_iter().next().map(rustls::PrivateKey))
+}
+
+/// Make sure the certificate chain starts with the leaf and every certificate
+/// is issued by the one following it, so intermediates reach the client.
+fn check_cert_chain(certs: &[rustls::Certificate]) -> Result<(), String> {
+    if certs.is_empty() {
+        return Err("No certificate found".to_string());
+    }
+    let mut parsed = Vec::with_capacity(certs.len());
+    for (i, cert) in certs.iter().enumerate() {
+        match x509_parser::parse_x509_certificate(&cert.0) {
+            Ok((_, cert)) => parsed.push(cert),
+            Err(e) => return Err(format!("Certificate #{} is invalid: {}", i + 1, e)),
+        }
+    }
+    for (i, pair) in parsed.windows(2).enumerate() {
+        if pair[0].issuer() == pair[1].subject() {
+            continue;
+        }
+        if pair[1].issuer() == pair[0].subject() {
+            return Err(format!(
+                "Certificate chain is out of order: #{} is issued by #{}, the leaf certificate must come first",
+                i + 2,
+                i + 1
+            ));
+        }
+        return Err(format!(
+            "Certificate chain is broken: #{} ({}) is not issued by #{} ({})",
+            i + 1,
+            pair[0].subject(),
+            i + 2,
+            pair[1].subject()
+        ));
+    }
+    Ok(())
+}
+
+#[inline]
+fn hash(from: &str) -> String {
+    let mut hasher = sha2::Sha512::new();
+    hasher.update(from);
+    format!("{:x}", hasher.finalize())
+}
+
+#[inline]
+async fn validator(
+    req: ServiceRequest,
+    auth: BasicAuth,
+) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
+    // Hash before looking the user up so unknown users cost the same as wrong passwords
+    let password = hash(auth.password().unwrap_or(&Cow::from("")));
+    let users = var("AUTH_USERS").unwrap_or_else(|_| "".to_string());
+    if users
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .any(|(username, hash)| username == auth.user_id() && hash == password)
+    {
+        return Ok(req);
+    }
+    let err = AuthenticationError::new(Basic::with_realm("Incorrect username or password"));
+    Err((actix_web::Error::from(err), req))
+}
+
+/// The whole service, configured through the env vars `main` sets.
+fn app() -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl actix_web::body::MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let app = App::new()
+        .wrap_fn(|req, srv| {
+            let location = normalized_path(req.path()).map(|path| match req.query_string() {
+                "" => path,
+                query => format!("{}?{}", path, query),
+            });
+            let fut = match location {
+                Some(location) => Err((req, location)),
+                None => Ok(srv.call(req)),
+            };
+            async move {
+                match fut {
+                    Ok(fut) => Ok(fut.await?.map_into_boxed_body()),
+                    Err((req, location)) => Ok(req.into_response(
+                        HttpResponse::PermanentRedirect()
+                            .insert_header((http::header::LOCATION, location))
+                            .finish(),
+                    )),
+                }
+            }
+        })
+        .wrap_fn(|req, srv| {
+            let size = req
+                .query_string()
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("thumb="))
+                .and_then(|size| size.parse::<u32>().ok())
+                .filter(|_| var("THUMBNAILS").unwrap_or_else(|_| "false".to_string()) == "true");
+            let path = urlencoding::decode(req.path())
+                .unwrap_or(Cow::Borrowed(""))
+                .into_owned();
+            let relative = Path::new(path.trim_start_matches('/'));
+            let path = size.filter(|_| is_plain(relative)).and_then(|_| {
+                layers()
+                    .iter()
+                    .map(|layer| layer.join(relative))
+                    .find(|path| path.is_file())
+            });
+            let fut = match (path, size) {
+                (Some(path), Some(size)) => Err((req, path, size)),
+                _ => Ok(srv.call(req)),
+            };
+            async move {
+                let (req, path, size) = match fut {
+                    Ok(fut) => return Ok(fut.await?.map_into_boxed_body()),
+                    Err(thumb) => thumb,
+                };
+                let thumbnail = actix_web::web::block(move || thumbnail::get(&path, size))
+                    .await
+                    .ok()
+                    .flatten();
+                let res = match thumbnail {
+                    Some((content_type, data)) => {
+                        HttpResponse::Ok().content_type(content_type).body(data)
+                    }
+                    None => HttpResponse::NotFound().finish(),
+                };
+                Ok(req.into_response(res))
+            }
+        })
+        .wrap_fn(|req, srv| {
+            let path = urlencoding::decode(req.path())
+                .unwrap_or(Cow::Borrowed(""))
+                .into_owned();
+            let relative = PathBuf::from(path.trim_start_matches('/'));
+            let tarball = req.method() == http::Method::GET
+                && req
+                    .query_string()
+                    .split('&')
+                    .any(|pair| pair == "download=tar.gz")
+                && is_plain(&relative)
+                && layers().iter().any(|layer| layer.join(&relative).is_dir())
+                // Nothing to download where nothing may be listed
+                && !is_noindex(&relative);
+            let fut = if tarball {
+                Err((req, relative))
+            } else {
+                Ok(srv.call(req))
+            };
+            async move {
+                let (req, relative) = match fut {
+                    Ok(fut) => return Ok(fut.await?.map_into_boxed_body()),
+                    Err(tarball) => tarball,
+                };
+                let root = PathBuf::from(var("ROOT").unwrap_or_else(|_| ".".to_string()));
+                let name = relative
+                    .file_name()
+                    .or_else(|| root.file_name())
+                    .map_or_else(
+                        || "root".to_string(),
+                        |name| name.to_string_lossy().into_owned(),
+                    );
+                let no_follow =
+                    var("NO_FOLLOW_SYMLINKS").unwrap_or_else(|_| "false".to_string()) == "true";
+                let body =
+                    archive::tar_gz(layers(), relative, name.clone(), !no_follow, move |path| {
+                        is_archived(path, no_follow)
+                    });
+                let res = HttpResponse::Ok()
+                    .content_type("application/gzip")
+                    .insert_header(http::header::ContentDisposition {
+                        disposition: http::header::DispositionType::Attachment,
+                        parameters: vec![http::header::DispositionParam::Filename(format!(
+                            "{}.tar.gz",
+                            name
+                        ))],
+                    })
+                    .body(body);
+                Ok(req.into_response(res))
+            }
+        })
+        .wrap_fn(|req, srv| {
+            let decoded = urlencoding::decode(req.path())
+                .unwrap_or(Cow::Borrowed(""))
+                .into_owned();
+            // Decoded, so `%2E` cannot sneak past, and split on both slashes like Windows does
+            let unescaped = urlencoding::decode_binary(req.path().as_bytes());
+            let unescaped = String::from_utf8_lossy(&unescaped).replace('\\', "/");
+            let isdotfile = is_hidden_dotfile(&unescaped);
+            let relative = Path::new(decoded.trim_start_matches('/'));
+            let excluded = is_excluded(relative);
+            // actix-files refuses `..` on its own, only plain paths are worth resolving
+            let plain = is_plain(relative);
+            let forbidden = plain && through_forbidden_symlink(relative);
+            let dotfiles = plain
+                .then(|| {
+                    let dir = relative.parent().unwrap_or_else(|| Path::new(""));
+                    dirconfig::resolve(&layers(), dir).dotfiles
+                })
+                .flatten()
+                .unwrap_or_else(|| {
+                    var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true"
+                });
+            let mime = Path::new(req.path())
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.to_ascii_lowercase())
+                .and_then(|ext| {
+                    var("MIME").unwrap_or_default().lines().find_map(|mapping| {
+                        let (key, mime) = mapping.split_once('=')?;
+                        (key == ext).then(|| mime.to_string())
+                    })
+                });
+            let download = var("DOWNLOAD").unwrap_or_else(|_| "false".to_string()) == "true"
+                || req
+                    .query_string()
+                    .split('&')
+                    .any(|pair| pair == "download=1");
+            let versioned = var("IMMUTABLE_QUERY").unwrap_or_else(|_| "false".to_string())
+                == "true"
+                && req
+                    .query_string()
+                    .split('&')
+                    .any(|pair| pair.strip_prefix("v=").is_some_and(|v| !v.is_empty()));
+            let fut = if excluded {
+                Err((req, HttpResponse::NotFound().finish()))
+            } else if forbidden || (isdotfile && !dotfiles) {
+                Err((req, HttpResponse::Forbidden().finish()))
+            } else {
+                Ok(srv.call(req))
+            };
+            async move {
+                let res = match fut {
+                    Ok(fut) => fut.await?,
+                    Err((req, res)) => return Ok(req.into_response(res)),
+                };
+                Ok(res.map_body(|head, body| {
+                    // Only files, listings and error pages keep their own type
+                    let is_file = head
+                        .headers()
+                        .contains_key(http::header::CONTENT_DISPOSITION);
+                    let mime = mime
+                        .filter(|_| is_file)
+                        .and_then(|mime| http::header::HeaderValue::from_str(&mime).ok());
+                    if let Some(mime) = mime {
+                        head.headers_mut().insert(http::header::CONTENT_TYPE, mime);
+                    }
+                    if var("NOCACHE").unwrap_or_else(|_| "false".to_string()) == "true" {
+                        head.headers_mut().insert(
+                            http::header::CACHE_CONTROL,
+                            http::header::HeaderValue::from_static("no-store"),
+                        );
+                    }
+                    // A `?v=<hash>` URL changes with the content, so it can be kept forever
+                    if versioned && is_file && head.status.is_success() {
+                        head.headers_mut().insert(
+                            http::header::CACHE_CONTROL,
+                            http::header::HeaderValue::from_static(
+                                "public, max-age=31536000, immutable",
+                            ),
+                        );
+                    }
+                    let compressed = head
+                        .headers()
+                        .get(http::header::CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<mime_guess::Mime>().ok())
+                        .is_some_and(|mime| filetype::is_compressed(&mime));
+                    if compressed && !head.headers().contains_key(http::header::CONTENT_ENCODING) {
+                        // The Compress middleware leaves encoded responses alone
+                        head.headers_mut().insert(
+                            http::header::CONTENT_ENCODING,
+                            http::header::HeaderValue::from_static("identity"),
+                        );
+                    }
+                    let disposition = head
+                        .headers()
+                        .get(http::header::CONTENT_DISPOSITION)
+                        .and_then(|value| http::header::ContentDisposition::from_raw(value).ok());
+                    if let (true, Some(disposition)) = (download, disposition) {
+                        // Keep the filename parameters actix-files already escaped
+                        let disposition = http::header::ContentDisposition {
+                            disposition: http::header::DispositionType::Attachment,
+                            ..disposition
+                        };
+                        if let Ok(value) =
+                            http::header::HeaderValue::from_str(&disposition.to_string())
+                        {
+                            head.headers_mut()
+                                .insert(http::header::CONTENT_DISPOSITION, value);
+                        }
+                    }
+                    body
+                }))
+            }
+        })
+        // actix-http fixes the levels (fast gzip and deflate, brotli and zstd at 3),
+        // with --compress the bodies are encoded here instead
+        .wrap(middleware::Condition::new(
+            var("NOCOMPRESS").unwrap_or_else(|_| "false".to_string()) != "true"
+                && var("COMPRESS_LEVEL").is_err(),
+            middleware::Compress::default(),
+        ))
+        .wrap_fn(|req, srv| {
+            let level = var("COMPRESS_LEVEL")
+                .ok()
+                .and_then(|level| level.parse::<u32>().ok());
+            let encoding = level.and_then(|_| compress::negotiate(&req));
+            let fut = srv.call(req);
+            async move {
+                let res = fut.await?.map_into_boxed_body();
+                Ok(match (level, encoding) {
+                    (Some(level), Some(encoding)) => res.map_body(|head, body| {
+                        actix_web::body::BoxBody::new(compress::Compressed::new(
+                            head, body, encoding, level,
+                        ))
+                    }),
+                    _ => res,
+                })
+            }
+        })
+        .wrap_fn(|req, srv| {
+            let fut = srv.call(req);
+            async {
+                let res = fut.await?.map_into_boxed_body();
+                let rate = var("LIMIT_RATE")
+                    .ok()
+                    .and_then(|rate| rate.parse::<u64>().ok())
+                    .unwrap_or(0);
+                // Only file bodies, actix-files is what sets Content-Disposition
+                if rate == 0
+                    || !res
+                        .headers()
+                        .contains_key(http::header::CONTENT_DISPOSITION)
+                {
+                    return Ok(res);
+                }
+                Ok(res.map_body(|_, body| {
+                    actix_web::body::BoxBody::new(throttle::Throttled::new(body, rate))
+                }))
+            }
+        })
+        .wrap_fn(|mut req, srv| {
+            let path = urlencoding::decode(req.path())
+                .unwrap_or(Cow::Borrowed(""))
+                .into_owned();
+            if matches_globs("NO_COMPRESS_PATH", Path::new(path.trim_start_matches('/'))) {
+                req.headers_mut().remove(http::header::ACCEPT_ENCODING);
+            }
+            // The Compress middleware cannot be limited, so it only gets to see allowed ones
+            let accept = req
+                .headers()
+                .get(http::header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(filter_encodings);
+            match accept.and_then(|accept| http::header::HeaderValue::from_str(&accept).ok()) {
+                Some(accept) if !accept.is_empty() => {
+                    req.headers_mut()
+                        .insert(http::header::ACCEPT_ENCODING, accept);
+                }
+                Some(_) => {
+                    req.headers_mut().remove(http::header::ACCEPT_ENCODING);
+                }
+                None => {}
+            }
+            srv.call(req)
+        })
+        .wrap(middleware::Condition::new(
+            var("ENABLE_AUTH").unwrap_or_else(|_| "false".to_string()) == "true",
+            HttpAuthentication::basic(validator),
+        ))
+        .wrap_fn(|req, srv| {
+            let authorized =
+                if var("ENABLE_AUTH_DIGEST").unwrap_or_else(|_| "false".to_string()) == "true" {
+                    digest::check(
+                        &req,
+                        &var("AUTH_DIGEST_USERNAME").unwrap_or_default(),
+                        &var("AUTH_DIGEST_HA1").unwrap_or_default(),
+                    )
+                } else {
+                    Ok(())
+                };
+            let fut = match authorized {
+                Ok(()) => Ok(srv.call(req)),
+                Err(stale) => Err((req, stale)),
+            };
+            async move {
+                match fut {
+                    Ok(fut) => Ok(fut.await?.map_into_boxed_body()),
+                    Err((req, stale)) => Ok(req.into_response(digest::challenge(stale))),
+                }
+            }
+        })
+        // Outside the auth layers, browsers never send credentials on a preflight
+        .wrap(middleware::Condition::new(
+            var("ENABLE_CORS").unwrap_or_else(|_| "false".to_string()) == "true",
+            {
+                let cors = actix_cors::Cors::default()
+                    .allow_any_method()
+                    .allow_any_header()
+                    .expose_any_header()
+                    .max_age(3600)
+                    // Other origins still get the response, just without CORS headers
+                    .block_on_origin_mismatch(false);
+                let cors = match var("CORS").unwrap_or_else(|_| "*".to_string()).as_str() {
+                    "*" => cors.allow_any_origin().send_wildcard(),
+                    origin => cors.allowed_origin(origin),
+                };
+                if var("CORS_CREDENTIALS").unwrap_or_else(|_| "false".to_string()) == "true" {
+                    cors.supports_credentials()
+                } else {
+                    cors
+                }
+            },
+        ))
+        .wrap(middleware::ErrorHandlers::new().default_handler(render_error))
+        // Outside the CORS and cache layers so user headers win on conflict
+        .wrap_fn(|req, srv| {
+            let fut = srv.call(req);
+            async {
+                let mut res = fut.await?;
+                let headers = var("HEADERS").unwrap_or_default();
+                let headers = headers.lines().filter_map(|header| {
+                    let (name, value) = header.split_once(':')?;
+                    Some((
+                        http::header::HeaderName::from_str(name).ok()?,
+                        http::header::HeaderValue::from_str(value).ok()?,
+                    ))
+                });
+                let headers = headers.collect::<Vec<_>>();
+                for (name, _) in &headers {
+                    res.headers_mut().remove(name);
+                }
+                for (name, value) in headers {
+                    res.headers_mut().append(name, value);
+                }
+                Ok(res)
+            }
+        })
+        .wrap_fn(|req, srv| {
+            let started = std::time::Instant::now();
+            let fut = srv.call(req);
+            async move {
+                let res = fut.await?.map_into_boxed_body();
+                if var("METRICS").unwrap_or_else(|_| "false".to_string()) != "true" {
+                    return Ok(res);
+                }
+                metrics::record(res.status(), started.elapsed());
+                Ok(res.map_body(|_, body| actix_web::body::BoxBody::new(metrics::Counted(body))))
+            }
+        })
+        .wrap_fn(|req, srv| {
+            // Plain connections only come in through the --https-redirect listener
+            let secure = req.app_config().secure();
+            let location = var("HTTPS_REDIRECT")
+                .ok()
+                .filter(|_| !secure)
+                .and_then(|_| {
+                    let info = req.connection_info();
+                    let host = info.host().parse::<http::uri::Authority>().ok()?;
+                    let port = var("LISTEN_ADDRESS")
+                        .ok()?
+                        .lines()
+                        .next()?
+                        .parse::<std::net::SocketAddr>()
+                        .ok()?
+                        .port();
+                    Some(format!(
+                        "https://{}{}{}",
+                        host.host(),
+                        if port == 443 {
+                            String::new()
+                        } else {
+                            format!(":{}", port)
+                        },
+                        req.uri().path_and_query().map_or("/", |path| path.as_str())
+                    ))
+                });
+            let fut = match location {
+                Some(location) => Err((req, location)),
+                None => Ok(srv.call(req)),
+            };
+            async move {
+                let mut res = match fut {
+                    Ok(fut) => fut.await?.map_into_boxed_body(),
+                    Err((req, location)) => {
+                        return Ok(req.into_response(
+                            HttpResponse::MovedPermanently()
+                                .insert_header((http::header::LOCATION, location))
+                                .finish(),
+                        ))
+                    }
+                };
+                let hsts = var("HSTS").ok().filter(|_| secure).and_then(|secs| {
+                    http::header::HeaderValue::from_str(&format!("max-age={}", secs)).ok()
+                });
+                if let Some(hsts) = hsts {
+                    res.headers_mut()
+                        .insert(http::header::STRICT_TRANSPORT_SECURITY, hsts);
+                }
+                Ok(res)
+            }
+        })
+        .wrap(middleware::Logger::new(&{
+            let format = var("LOG_FORMAT").unwrap_or_else(|_| DEFAULT_LOG_FORMAT.to_string());
+            // Custom formats get the status prepended so --log-status can filter them
+            if format == DEFAULT_LOG_FORMAT || format == JSON_LOG_FORMAT {
+                format
+            } else {
+                format!("%s^{}", format)
+            }
+        }))
+        // Outside the logger, so `%a` and everything below see the client behind the proxy
+        .wrap_fn(|mut req, srv| {
+            let peer = req.head().peer_addr;
+            let client = peer
+                .filter(|_| var("TRUSTED_PROXIES").is_ok())
+                .and_then(|peer| realip::client_ip(peer.ip(), req.headers()));
+            if let (Some(peer), Some(client)) = (peer, client) {
+                req.extensions_mut().insert(realip::Peer(peer));
+                req.head_mut().peer_addr = Some(std::net::SocketAddr::new(client, peer.port()));
+            }
+            srv.call(req)
+        });
+    let app = if var("METRICS").unwrap_or_else(|_| "false".to_string()) == "true" {
+        app.route(
+            metrics::METRICS_PATH,
+            actix_web::web::get().to(metrics::metrics),
+        )
+    } else {
+        app
+    };
+    let proxies = var("PROXY").unwrap_or_default();
+    let app = if proxies.is_empty() {
+        app
+    } else {
+        app.app_data(actix_web::web::Data::new(awc::Client::default()))
+    };
+    // Registered before the files so their prefixes take precedence
+    let app = proxies
+        .lines()
+        .fold(app, |app, proxy| match proxy.split_once('=') {
+            Some((prefix, upstream)) => app.service(proxy::scope(prefix, upstream)),
+            None => app,
+        });
+    if let Ok(file) = var("SINGLE_FILE") {
+        return app.default_service(move |req: ServiceRequest| {
+            let file = file.clone();
+            async move {
+                let (http_req, _payload) = req.into_parts();
+                let res = if http_req.path() != "/" {
+                    HttpResponse::NotFound().finish()
+                } else if !matches!(*http_req.method(), http::Method::GET | http::Method::HEAD) {
+                    HttpResponse::MethodNotAllowed().finish()
+                } else {
+                    actix_files::NamedFile::open(file)?.into_response(&http_req)
+                };
+                Ok(ServiceResponse::new(http_req, res))
+            }
+        });
+    }
+    let mut files = actix_files::Files::new("/", var("ROOT").unwrap_or_else(|_| ".".to_string()))
+        .use_hidden_files()
+        .prefer_utf8(true)
+        .show_files_listing()
+        .files_listing_renderer(render_index)
+        .default_handler(|req: ServiceRequest| {
+            let (http_req, _payload) = req.into_parts();
+            async {
+                // Only reached when no layer has its own favicon.ico
+                if http_req.path() == "/favicon.ico"
+                    && var("NOFAVICON").unwrap_or_else(|_| "false".to_string()) != "true"
+                {
+                    let res = HttpResponse::Ok()
+                        .content_type("image/x-icon")
+                        .body(FAVICON);
+                    return Ok(ServiceResponse::new(http_req, res));
+                }
+                if var("CLEAN_URLS").unwrap_or_else(|_| "false".to_string()) == "true" {
+                    let path = layers()
+                        .iter()
+                        .find_map(|layer| resolve_clean_url(layer, http_req.path()));
+                    if let Some(path) = path {
+                        let res = actix_files::NamedFile::open(path)?.into_response(&http_req);
+                        return Ok(ServiceResponse::new(http_req, res));
+                    }
+                }
+                // An index in a higher layer wins like everywhere else
+                let path = index_names().iter().find_map(|index| {
+                    layers()
+                        .iter()
+                        .map(|layer| layer.join(index))
+                        .find(|path| path.is_file())
+                });
+                if let Some(path) =
+                    path.filter(|_| var("SPA").unwrap_or_else(|_| "false".to_string()) == "true")
+                {
+                    let res = actix_files::NamedFile::open(path)?.into_response(&http_req);
+                    return Ok(ServiceResponse::new(http_req, res));
+                }
+                Ok(ServiceResponse::new(
+                    http_req,
+                    HttpResponse::NotFound().body(""),
+                ))
+            }
+        });
+    // Each overlay falls through to the layer below when a path is missing
+    for overlay in var("OVERLAY").unwrap_or_default().lines().rev() {
+        files = actix_files::Files::new("/", overlay)
+            .use_hidden_files()
+            .prefer_utf8(true)
+            .show_files_listing()
+            .files_listing_renderer(render_index)
+            .default_handler(files);
+    }
+    if var("WATCH").unwrap_or_else(|_| "false".to_string()) == "true" {
+        app.route(
+            watch::LIVERELOAD_PATH,
+            actix_web::web::get().to(watch::livereload),
+        )
+        .service(files)
+    } else {
+        app.service(files)
     }
-    let err = AuthenticationError::new(Basic::with_realm("Incorrect username or password"));
-    Err((actix_web::Error::from(err), req))
 }
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
-    let check_does_dir_exits = |path: &str| match metadata(path) {
-        Ok(meta) => {
-            if meta.is_dir() {
-                Ok(path.to_string())
-            } else {
-                Err("Parameter is not a directory".to_owned())
+    let check_does_dir_exits = |path: &str| {
+        let path = expand_path(path)?;
+        match metadata(&path) {
+            Ok(meta) => {
+                if meta.is_dir() {
+                    Ok(path)
+                } else {
+                    Err("Parameter is not a directory".to_owned())
+                }
             }
+            Err(e) => Err(e.to_string()),
         }
-        Err(e) => Err(e.to_string()),
     };
-    let check_does_file_exits = |path: &str| match metadata(path) {
-        Ok(metadata) => {
-            if metadata.is_file() {
-                Ok(path.to_string())
-            } else {
-                Err("Parameter is not a file".to_owned())
+    let check_does_path_exits = |path: &str| {
+        let path = expand_path(path)?;
+        match metadata(&path) {
+            Ok(meta) => {
+                if meta.is_dir() || meta.is_file() {
+                    Ok(path)
+                } else {
+                    Err("Parameter is not a directory or a file".to_owned())
+                }
             }
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    let check_does_file_exits = |path: &str| {
+        let path = expand_path(path)?;
+        match metadata(&path) {
+            Ok(metadata) => {
+                if metadata.is_file() {
+                    Ok(path)
+                } else {
+                    Err("Parameter is not a file".to_owned())
+                }
+            }
+            Err(e) => Err(e.to_string()),
         }
-        Err(e) => Err(e.to_string()),
     };
     let check_is_ip_addr = |s: &str| match IpAddr::from_str(s) {
         Ok(_) => Ok(s.to_string()),
@@ -267,6 +1830,72 @@ async fn main() -> io::Result<()> {
         Ok(_) => Ok(s.to_string()),
         Err(e) => Err(e.to_string()),
     };
+    let check_is_glob = |s: &str| match globset::Glob::new(s) {
+        Ok(_) => Ok(s.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let check_is_mime = |s: &str| match s.split_once('=') {
+        Some((ext, mime)) if !ext.trim_start_matches('.').is_empty() => {
+            match mime.parse::<mime_guess::Mime>() {
+                Ok(_) => Ok(format!(
+                    "{}={}",
+                    ext.trim_start_matches('.').to_ascii_lowercase(),
+                    mime
+                )),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        _ => Err("Expected <ext>=<type>, e.g. wasm=application/wasm".to_owned()),
+    };
+    let check_is_header = |s: &str| match s.split_once(':') {
+        Some((name, value)) => {
+            let name =
+                http::header::HeaderName::from_str(name.trim()).map_err(|e| e.to_string())?;
+            let value =
+                http::header::HeaderValue::from_str(value.trim()).map_err(|e| e.to_string())?;
+            Ok(format!(
+                "{}:{}",
+                name,
+                value.to_str().map_err(|e| e.to_string())?
+            ))
+        }
+        None => Err("Expected <name>: <value>, e.g. 'X-Frame-Options: DENY'".to_owned()),
+    };
+    let check_is_proxy = |s: &str| match s.split_once('=') {
+        Some((prefix, upstream)) if prefix.starts_with('/') => {
+            match upstream.parse::<http::Uri>() {
+                Ok(uri) if matches!(uri.scheme_str(), Some("http" | "https")) => {
+                    Ok(format!("{}={}", prefix.trim_end_matches('/'), upstream))
+                }
+                Ok(_) => Err("Upstream must be an http:// or https:// URL".to_owned()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        _ => Err("Expected <prefix>=<upstream>, e.g. /api=http://localhost:3000".to_owned()),
+    };
+    let check_is_cidrs = |s: &str| {
+        for cidr in s.split(',') {
+            realip::parse_cidr(cidr)?;
+        }
+        Ok::<_, String>(s.to_string())
+    };
+    let check_is_encodings = |s: &str| {
+        let encodings = s
+            .split(',')
+            .map(|encoding| encoding.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        match encodings
+            .iter()
+            .find(|encoding| !COMPRESS_ENCODINGS.contains(&encoding.as_str()))
+        {
+            Some(encoding) => Err(format!("Unknown encoding: {}", encoding)),
+            None => Ok(encodings.join(",")),
+        }
+    };
+    let check_is_status_spec = |s: &str| match status_matches(s, 0) {
+        Ok(_) => Ok(s.to_string()),
+        Err(e) => Err(e),
+    };
     let check_is_auth = |s: &str| {
         let parts = s.splitn(2, ':').collect::<Vec<&str>>();
         if parts.len() < 2 || parts.len() >= 2 && parts[1].is_empty() {
@@ -277,29 +1906,92 @@ async fn main() -> io::Result<()> {
             Ok(s.to_string())
         }
     };
+    let check_is_auth_file = |path: &str| {
+        let contents = read_to_string(expand_path(path)?).map_err(|e| e.to_string())?;
+        let mut users = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((username, hash))
+                    if !username.is_empty()
+                        && hash.len() == 128
+                        && hash.chars().all(|c| c.is_ascii_hexdigit()) =>
+                {
+                    users.push(format!("{}:{}", username, hash.to_ascii_lowercase()));
+                }
+                _ => return Err(format!("Line {}: expected username:sha512-hex", i + 1)),
+            }
+        }
+        Ok(users.join("\n"))
+    };
     let matches = command!()
         .arg(arg!(--noindex "Disable automatic index page generation").required(false))
         .arg(arg!(--noreadme "Disable automatic readme rendering").required(false))
         .arg(arg!(--nocache "Disable HTTP cache").required(false))
+        .arg(arg!(--"immutable-query" "Cache files requested with a ?v=<hash> query for a year as immutable").required(false).conflicts_with("nocache"))
         .arg(arg!(--nocolor "Disable cli colors").required(false))
+        .arg(arg!(--nocompress "Disable response compression").required(false))
+        .arg(arg!(--compress <level> "Compression level from 1 (fastest) to 9 (smallest) for every algorithm, instead of actix-web's fast defaults").required(false).value_parser(clap::value_parser!(u32).range(1..=9)).conflicts_with("nocompress"))
+        .arg(arg!(--encodings <list> "Compression algorithms to offer, comma separated: br, gzip, deflate, zstd").required(false).value_parser(check_is_encodings))
         .arg(arg!(--cors [hostname] "Enable CORS [with custom value]").required(false).action(ArgAction::Append))
+        .arg(arg!(--"cors-credentials" "Allow credentialed CORS requests (needs a specific --cors origin)").requires("cors"))
+        .arg(arg!(--"normalize-path" "Redirect directories to their trailing slash form and collapse duplicate slashes").required(false))
+        .arg(arg!(--"clean-urls" "Serve /about from about.html or about/index.html when it does not exist").required(false))
         .arg(arg!(--spa "Enable Single-Page Application mode (always serve /index.html when the file is not found)").required(false))
+        .arg(arg!(--index <name> "Serve this file in place of a directory listing (repeatable, first wins)").required(false).action(ArgAction::Append).default_value("index.html"))
         .arg(arg!(-d --dotfiles "Show dotfiles").required(false))
+        .arg(arg!(--"show-dotfile" <glob> "Show and serve dotfiles matching the glob even without --dotfiles (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_glob))
+        .arg(arg!(--"no-favicon" "Do not serve the built-in favicon when the root has none").required(false))
+        .arg(arg!(--"no-follow-symlinks" "Refuse to serve paths through symlinks and list symlinks as such").required(false))
+        .arg(arg!(--mime <mapping> "Serve files with this extension as this content type, e.g. wasm=application/wasm (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_mime))
+        .arg(arg!(--header <header> "Add this header to every response, e.g. 'X-Frame-Options: DENY' (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_header))
+        .arg(arg!(--proxy <mapping> "Forward requests under this prefix to an upstream, e.g. /api=http://localhost:3000 (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_proxy))
+        .arg(arg!(--metrics "Expose Prometheus metrics at /metrics").required(false))
+        .arg(arg!(--"limit-rate" <bytes> "Limit each file download to this many bytes per second, 0 for unlimited").required(false).value_parser(clap::value_parser!(u64)))
+        .arg(arg!(--workers <n> "Number of worker threads, 0 for one per logical CPU").required(false).value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--"keep-alive" <secs> "Seconds an idle connection is kept open, 0 to close after each response").default_value("5").value_parser(clap::value_parser!(u64)))
+        .arg(arg!(--"client-timeout" <secs> "Seconds a client has to send the request head, 0 for no limit").default_value("5").value_parser(clap::value_parser!(u64)))
+        .arg(arg!(--"page-size" <count> "Entries per page in directory listings, 0 to disable paging").default_value("500").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--template <path> "Render directory listings with this Tera template instead of the built-in one").required(false).value_parser(check_does_file_exits))
+        .arg(arg!(--theme <theme> "Color scheme of directory listings").default_value("auto").value_parser(["auto", "dark", "light"]))
+        .arg(arg!(--"time-format" <format> "How modified times are shown in directory listings").default_value("absolute").value_parser(["absolute", "relative"]))
+        .arg(arg!(--thumbnails "Show image thumbnails in directory listings").required(false))
+        .arg(arg!(--download "Serve files as attachments so browsers download them").required(false))
         .arg(arg!(-o --open "Open the page in the default browser").required(false))
+        .arg(arg!(--qr "Print a QR code of the server URL on startup").required(false))
+        .arg(arg!(-v --verbose "Print the effective configuration on startup").required(false))
         .arg(arg!(-q --quiet "Disable access log output").required(false))
         .arg(arg!(--quietall "Disable all output").required(false))
-        .arg(arg!([root] "Root directory").default_value(".").value_parser(check_does_dir_exits))
-        .arg(arg!(-a --address <ipaddr> "IP address to serve on").default_value("0.0.0.0").value_parser(check_is_ip_addr))
-        .arg(arg!(-p --port <port> "Port to serve on").default_value("8000").value_parser(check_is_port_num))
-        .arg(arg!(--auth <pattern> "HTTP Auth (username:password)").required(false).value_parser(check_is_auth))
+        .arg(arg!(--"log-file" <path> "Also write access logs to a file (rotated at 10 MB, keeping 5)").required(false).value_parser(expand_path))
+        .arg(arg!(--"behind-proxy" [cidrs] "Log and use the client address from X-Forwarded-For/X-Real-IP sent by these proxies (comma separated) [default: 127.0.0.0/8,::1/128]").required(false).value_parser(check_is_cidrs))
+        .arg(arg!(--"log-status" <spec> "Only log responses matching the status spec, e.g. 4xx,5xx or >=400").required(false).value_parser(check_is_status_spec))
+        .arg(arg!(--"log-format" <format> "Custom access log format (actix logger syntax), or a preset: common, combined").required(false))
+        .arg(arg!(--"log-json" "Write access logs as one JSON object per line").required(false).conflicts_with("log-format"))
+        .arg(arg!(--exclude <glob> "Hide and refuse to serve paths matching the glob (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_glob))
+        .arg(arg!(--"no-compress-path" <glob> "Never compress paths matching the glob (repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_glob))
+        .arg(arg!(--overlay <dir> "Serve files from this directory over the root directory (repeatable, first wins)").required(false).action(ArgAction::Append).value_parser(check_does_dir_exits))
+        .arg(arg!([root] "Root directory, or a single file to serve at /").default_value(".").value_parser(check_does_path_exits))
+        .arg(arg!(-a --address <ipaddr> "IP address to serve on (repeatable)").default_value("0.0.0.0").action(ArgAction::Append).value_parser(check_is_ip_addr))
+        .arg(arg!(-p --port <port> "Port to serve on (repeatable, every address listens on every port)").default_value("8000").action(ArgAction::Append).value_parser(check_is_port_num))
+        .arg(arg!(--auth <pattern> "HTTP Auth (username:password, repeatable)").required(false).action(ArgAction::Append).value_parser(check_is_auth))
+        .arg(arg!(--"auth-file" <path> "HTTP Auth users file (username:sha512-hex-of-password per line)").required(false).value_parser(check_is_auth_file))
+        .arg(arg!(--"auth-digest" <pattern> "HTTP Digest Auth (username:password)").required(false).value_parser(check_is_auth).conflicts_with_all(["auth", "auth-file"]))
         .arg(arg!(--cert <path> "Path of TLS/SSL public key (certificate)").required(false).value_parser(check_does_file_exits))
         .arg(arg!(--key <path> "Path of TLS/SSL private key").required(false).value_parser(check_does_file_exits))
+        .arg(arg!(--force "Serve a home directory or a filesystem root").required(false))
+        .arg(arg!(--"https-redirect" [port] "Also listen on this plain HTTP port and redirect everything to HTTPS").required(false).default_missing_value("80").value_parser(check_is_port_num).requires("cert").requires("key"))
+        .arg(arg!(--hsts [secs] "Send Strict-Transport-Security with this max-age on HTTPS responses").required(false).default_missing_value("31536000").value_parser(clap::value_parser!(u64)).requires("cert").requires("key"))
         .subcommand(clap::Command::new("doc")
             .about("Open cargo doc via local server (Need cargo installation)")
             .arg(arg!(--nocolor "Disable cli colors"))
             .arg(arg!(--noopen "Do not open the page in the default browser"))
             .arg(arg!(--log "Enable access log output [default: disabled]"))
             .arg(arg!(--quietall "Disable all output"))
+            .arg(arg!(--watch "Regenerate the document and reload the page when sources change"))
+            .arg(arg!(--package <name> "Package to document in a workspace").required(false))
             .arg(arg!(-a --address <ipaddr> "IP address to serve on").required(false).default_value("0.0.0.0").value_parser(check_is_ip_addr))
             .arg(arg!(-p --port <port> "Port to serve on").required(false).default_value("8000").value_parser(check_is_port_num))
         )
@@ -314,11 +2006,111 @@ async fn main() -> io::Result<()> {
         )),
     );
 
+    let root = Path::new(matches.get_one::<String>("root").unwrap());
+    if root.is_file() {
+        set_var("SINGLE_FILE", display_path(root));
+    }
+
+    if let Some(overlays) = matches.get_many::<String>("overlay") {
+        let overlays = overlays.map(|overlay| display_path(Path::new(overlay)));
+        set_var("OVERLAY", overlays.collect::<Vec<_>>().join("\n"));
+    }
+
+    if let Some(index) = matches.get_many::<String>("index") {
+        set_var("INDEX", index.cloned().collect::<Vec<_>>().join("\n"));
+    }
     set_var("NOINDEX", matches.get_flag("noindex").to_string());
     set_var("NOREADME", matches.get_flag("noreadme").to_string());
     set_var("SPA", matches.get_flag("spa").to_string());
+    set_var("CLEAN_URLS", matches.get_flag("clean-urls").to_string());
+    set_var(
+        "NORMALIZE_PATH",
+        matches.get_flag("normalize-path").to_string(),
+    );
     set_var("DOTFILES", matches.get_flag("dotfiles").to_string());
+    set_var("NOFAVICON", matches.get_flag("no-favicon").to_string());
+    set_var(
+        "NO_FOLLOW_SYMLINKS",
+        matches.get_flag("no-follow-symlinks").to_string(),
+    );
+    set_var("DOWNLOAD", matches.get_flag("download").to_string());
+    set_var("THUMBNAILS", matches.get_flag("thumbnails").to_string());
+    set_var("METRICS", matches.get_flag("metrics").to_string());
+    if let Some(mime) = matches.get_many::<String>("mime") {
+        set_var("MIME", mime.cloned().collect::<Vec<_>>().join("\n"));
+    }
+    if matches.contains_id("behind-proxy") {
+        let trusted = matches.get_one::<String>("behind-proxy");
+        set_var(
+            "TRUSTED_PROXIES",
+            trusted.map_or(realip::DEFAULT_TRUSTED, String::as_str),
+        );
+    }
+    if let Some(port) = matches.get_one::<String>("https-redirect") {
+        set_var("HTTPS_REDIRECT", port);
+    }
+    if let Some(secs) = matches.get_one::<u64>("hsts") {
+        set_var("HSTS", secs.to_string());
+    }
+    if let Some(proxy) = matches.get_many::<String>("proxy") {
+        set_var("PROXY", proxy.cloned().collect::<Vec<_>>().join("\n"));
+    }
+    if let Some(headers) = matches.get_many::<String>("header") {
+        set_var("HEADERS", headers.cloned().collect::<Vec<_>>().join("\n"));
+    }
+    set_var("THEME", matches.get_one::<String>("theme").unwrap());
+    set_var(
+        "TIME_FORMAT",
+        matches.get_one::<String>("time-format").unwrap(),
+    );
+    if let Some(rate) = matches.get_one::<u64>("limit-rate") {
+        set_var("LIMIT_RATE", rate.to_string());
+    }
+    set_var(
+        "PAGE_SIZE",
+        matches.get_one::<usize>("page-size").unwrap().to_string(),
+    );
+    if let Some(workers) = matches.get_one::<usize>("workers").filter(|n| **n > 0) {
+        set_var("WORKERS", workers.to_string());
+    }
+    set_var(
+        "KEEP_ALIVE",
+        matches.get_one::<u64>("keep-alive").unwrap().to_string(),
+    );
+    set_var(
+        "CLIENT_TIMEOUT",
+        matches
+            .get_one::<u64>("client-timeout")
+            .unwrap()
+            .to_string(),
+    );
     set_var("NOCACHE", matches.get_flag("nocache").to_string());
+    set_var(
+        "IMMUTABLE_QUERY",
+        matches.get_flag("immutable-query").to_string(),
+    );
+    set_var("NOCOMPRESS", matches.get_flag("nocompress").to_string());
+    if let Some(level) = matches.get_one::<u32>("compress") {
+        set_var("COMPRESS_LEVEL", level.to_string());
+    }
+    if let Some(encodings) = matches.get_one::<String>("encodings") {
+        set_var("ENCODINGS", encodings);
+    }
+    if let Some(allowed) = matches.get_many::<String>("show-dotfile") {
+        set_var(
+            "SHOW_DOTFILE",
+            allowed.cloned().collect::<Vec<_>>().join("\n"),
+        );
+    }
+    if let Some(exclude) = matches.get_many::<String>("exclude") {
+        set_var("EXCLUDE", exclude.cloned().collect::<Vec<_>>().join("\n"));
+    }
+    if let Some(paths) = matches.get_many::<String>("no-compress-path") {
+        set_var(
+            "NO_COMPRESS_PATH",
+            paths.cloned().collect::<Vec<_>>().join("\n"),
+        );
+    }
 
     if matches.get_flag("quiet") {
         set_var("RUST_LOG", "info,actix_web::middleware::logger=off");
@@ -326,50 +2118,114 @@ async fn main() -> io::Result<()> {
     if matches.get_flag("quietall") {
         set_var("RUST_LOG", "off");
     }
-    if matches.get_flag("nocolor") {
+    if matches.get_flag("nocolor") || matches.get_flag("log-json") {
         set_var("RUST_LOG_STYLE", "never");
     }
+    set_var(
+        "LOG_FORMAT",
+        match matches.get_one::<String>("log-format").map(|s| s.as_str()) {
+            Some("common") => r#"%a - - [%t] "%r" %s %b"#,
+            Some("combined") => r#"%a - - [%t] "%r" %s %b "%{Referer}i" "%{User-Agent}i""#,
+            Some(format) => format,
+            None if matches.get_flag("log-json") => JSON_LOG_FORMAT,
+            None => DEFAULT_LOG_FORMAT,
+        },
+    );
 
-    if let Some(s) = matches.get_one::<String>("auth") {
-        set_var("ENABLE_AUTH", matches.get_flag("auth").to_string());
+    let mut users = vec![];
+    for s in matches.get_many::<String>("auth").into_iter().flatten() {
         let parts = s.splitn(2, ':').collect::<Vec<&str>>();
-        set_var("AUTH_USERNAME", parts[0]);
-        set_var("AUTH_PASSWORD", hash(parts[1]));
+        users.push(format!("{}:{}", parts[0], hash(parts[1])));
     }
-
-    if let Some(mut cors) = matches.get_many::<String>("cors") {
-        set_var("ENABLE_CORS", "true");
-        match cors.next() {
-            Some(value) => set_var("CORS", value),
-            None => set_var("CORS", "*"),
-        }
+    if let Some(s) = matches.get_one::<String>("auth-file") {
+        users.push(s.to_string());
+    }
+    if !users.is_empty() {
+        set_var("ENABLE_AUTH", "true");
+        set_var("AUTH_USERS", users.join("\n"));
+    }
+    if let Some(s) = matches.get_one::<String>("auth-digest") {
+        set_var("ENABLE_AUTH_DIGEST", "true");
+        let parts = s.splitn(2, ':').collect::<Vec<&str>>();
+        set_var("AUTH_DIGEST_USERNAME", parts[0]);
+        set_var("AUTH_DIGEST_HA1", digest::ha1(parts[0], parts[1]));
     }
 
     let enable_tls =
         matches.get_one::<String>("cert").is_some() && matches.get_one::<String>("key").is_some();
-    let ip = matches
-        .get_one::<String>("address")
-        .unwrap_or(&"127.0.0.1".to_string())
-        .to_string();
-    let addr = format!(
-        "{}:{}",
-        ip,
-        matches
-            .get_one::<String>("port")
-            .unwrap_or(&"8000".to_string())
-    );
-    let url = format!(
-        "{}{}:{}",
-        if enable_tls {
-            "https://".to_string()
+    let ips = matches
+        .get_many::<String>("address")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    let ports = matches
+        .get_many::<String>("port")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    // Every address on every port, the first one is what the URLs point at
+    let addr = ips
+        .iter()
+        .flat_map(|ip| {
+            ports.iter().filter_map(move |port| {
+                Some(std::net::SocketAddr::new(ip.parse().ok()?, port.parse().ok()?).to_string())
+            })
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let ip = ips
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "0.0.0.0".to_string());
+    let lan = if ip == "0.0.0.0" {
+        interfaces::lan_addresses()
+    } else {
+        vec![]
+    };
+    let urls = lan
+        .iter()
+        .map(|lan| lan.to_string())
+        .chain(std::iter::once(if ip == "0.0.0.0" {
+            // Nothing reachable from other devices, fall back to this machine only
+            "127.0.0.1".to_string()
         } else {
-            "http://".to_string()
-        },
-        if ip == "0.0.0.0" { "127.0.0.1" } else { &ip },
-        matches
-            .get_one::<String>("port")
-            .unwrap_or(&"8000".to_string())
-    );
+            ip.clone()
+        }))
+        .map(|host| {
+            format!(
+                "{}{}:{}",
+                if enable_tls { "https://" } else { "http://" },
+                host,
+                matches
+                    .get_one::<String>("port")
+                    .unwrap_or(&"8000".to_string())
+            )
+        })
+        .collect::<Vec<_>>();
+    let url = urls[0].clone();
+    if !lan.is_empty() {
+        set_var("LAN_URLS", urls[..lan.len()].join("\n"));
+    }
+
+    if matches.get_flag("qr") {
+        match qrcode::QrCode::new(&url) {
+            Ok(code) => set_var(
+                "QR_CODE",
+                format!(
+                    "{}\n{}",
+                    code.render::<qrcode::render::unicode::Dense1x2>()
+                        // Terminals are usually dark, draw the modules in the background color
+                        .dark_color(qrcode::render::unicode::Dense1x2::Light)
+                        .light_color(qrcode::render::unicode::Dense1x2::Dark)
+                        .build(),
+                    url
+                ),
+            ),
+            Err(e) => set_var("QR_CODE", format!("Failed to encode {}: {}", url, e)),
+        }
+    }
 
     let open_in_browser = |url: &str| {
         if cfg!(target_os = "windows") {
@@ -388,7 +2244,7 @@ async fn main() -> io::Result<()> {
     };
 
     if matches.get_flag("open") {
-        open_in_browser(&url);
+        set_var("OPEN_URL", &url);
     }
 
     if let Some(matches) = matches.subcommand_matches("doc") {
@@ -403,6 +2259,18 @@ async fn main() -> io::Result<()> {
         }
     }
 
+    let json_log_format = var("LOG_FORMAT").unwrap_or_default() == JSON_LOG_FORMAT;
+    let custom_log_format =
+        var("LOG_FORMAT").unwrap_or_default() != DEFAULT_LOG_FORMAT && !json_log_format;
+    let log_status = matches.get_one::<String>("log-status").cloned();
+    let log_file = matches
+        .get_one::<String>("log-file")
+        .map(|path| logfile::LogFile::open(Path::new(path)));
+    let (log_file, log_file_error) = match log_file {
+        Some(Ok(log_file)) => (Some(log_file), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format(move |buf, record| {
             let data = record.args().to_string();
@@ -412,54 +2280,129 @@ async fn main() -> io::Result<()> {
             let red = style.set_color(Color::Red);
             let mut style = buf.style();
             let green = style.set_color(Color::Green);
-            if record.target() == "actix_web::middleware::logger" {
+            if record.target() == "actix_web::middleware::logger" && custom_log_format {
+                let (status_code, data) = data.split_once('^').unwrap_or(("", &data));
+                if let (Some(spec), Ok(status_code)) = (&log_status, status_code.parse()) {
+                    if !status_matches(spec, status_code).unwrap_or(true) {
+                        return Ok(());
+                    }
+                }
+                if let Some(log_file) = &log_file {
+                    log_file.write_line(data).ok();
+                }
+                return writeln!(buf, "{}", data);
+            } else if record.target() == "actix_web::middleware::logger" && json_log_format {
+                let data: Vec<&str> = data.splitn(6, '^').collect();
+                if data.len() < 6 {
+                    return Ok(());
+                }
+                let status_code: u16 = data[2].parse().unwrap_or(500);
+                if let Some(spec) = &log_status {
+                    if !status_matches(spec, status_code).unwrap_or(true) {
+                        return Ok(());
+                    }
+                }
+                // `%r` is the request line, e.g. `GET /path HTTP/1.1`
+                let mut request = data[5].splitn(3, ' ');
+                let method = request.next().unwrap_or_default();
+                let path = urlencoding::decode(request.next().unwrap_or_default())
+                    .map(Cow::into_owned)
+                    .unwrap_or_default();
+                let line = serde_json::json!({
+                    "time": data[0],
+                    "ip": data[1],
+                    "status": status_code,
+                    "duration_ms": data[3].parse::<f64>().unwrap_or_default(),
+                    "method": method,
+                    "path": path,
+                    "bytes": data[4].parse::<u64>().unwrap_or_default(),
+                })
+                .to_string();
+                if let Some(log_file) = &log_file {
+                    log_file.write_line(&line).ok();
+                }
+                return writeln!(buf, "{}", line);
+            } else if record.target() == "actix_web::middleware::logger" {
                 let data: Vec<&str> = data.splitn(5, '^').collect();
-                let time = blue.value(
+                let time =
                     OffsetDateTime::parse(data[0], &time::format_description::well_known::Rfc3339)
                         .unwrap_or(OffsetDateTime::UNIX_EPOCH)
                         .format(time::macros::format_description!(
                             "[year]/[month]/[day] [hour]:[minute]:[second]"
                         ))
-                        .unwrap_or_else(|_| "".to_string()),
-                );
-                let ipaddr = blue.value(data[1]);
-                let status_code = data[2].parse().unwrap_or(500);
-                let status_code = if status_code < 400 {
-                    green.value(status_code)
-                } else {
-                    red.value(status_code)
-                };
+                        .unwrap_or_else(|_| "".to_string());
+                let ipaddr = data[1];
+                let status_code: u16 = data[2].parse().unwrap_or(500);
+                if let Some(spec) = &log_status {
+                    if !status_matches(spec, status_code).unwrap_or(true) {
+                        return Ok(());
+                    }
+                }
                 let process_time: Vec<&str> = data[3].splitn(2, '.').collect();
                 let process_time = process_time[0].to_string() + "ms";
-                let process_time = blue.value(if process_time.len() == 3 {
+                let process_time = if process_time.len() == 3 {
                     "  ".to_string() + &process_time
                 } else if process_time.len() == 4 {
                     " ".to_string() + &process_time
                 } else {
                     process_time
-                });
-                let content = blue.value(
-                    urlencoding::decode(data[4])
-                        .unwrap_or(Cow::Borrowed("[Parse URL Error]"))
-                        .into_owned(),
-                );
+                };
+                let content = urlencoding::decode(data[4])
+                    .unwrap_or(Cow::Borrowed("[Parse URL Error]"))
+                    .into_owned();
+                if let Some(log_file) = &log_file {
+                    log_file
+                        .write_line(&format!(
+                            "[{}] {} | {} | {} | {}",
+                            time, ipaddr, status_code, process_time, content
+                        ))
+                        .ok();
+                }
                 return writeln!(
                     buf,
                     "[{}] {} | {} | {} | {}",
-                    time, ipaddr, status_code, process_time, content
+                    blue.value(time),
+                    blue.value(ipaddr),
+                    if status_code < 400 {
+                        green.value(status_code)
+                    } else {
+                        red.value(status_code)
+                    },
+                    blue.value(process_time),
+                    blue.value(content)
                 );
             } else if record.target() == "actix_server::builder" {
                 if data.starts_with("Starting ") && data.ends_with(" workers") {
-                    return Ok(());
+                    // Only worth a line when the count was picked by hand
+                    if var("WORKERS").is_err() {
+                        return Ok(());
+                    }
+                    return writeln!(buf, "\r{}", green.value(format!("[INFO] {}", data)));
                 }
             } else if record.target() == "actix_server::server" {
                 if data == "Actix runtime found; starting in Actix runtime" {
                     let data = format!(
                         "[INFO] Serving {} on {}",
-                        var("ROOT").unwrap_or_else(|_| ".".to_string()),
-                        var("LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8000".to_string())
+                        match var("SINGLE_FILE") {
+                            Ok(file) => format!("file {}", file),
+                            Err(_) => var("ROOT").unwrap_or_else(|_| ".".to_string()),
+                        },
+                        var("LISTEN_ADDRESS")
+                            .unwrap_or_else(|_| "0.0.0.0:8000".to_string())
+                            .replace('\n', ", ")
                     );
-                    return writeln!(buf, "\r{}", green.value(data));
+                    writeln!(buf, "\r{}", green.value(data))?;
+                    for url in var("LAN_URLS").unwrap_or_default().lines() {
+                        writeln!(
+                            buf,
+                            "{}",
+                            green.value(format!("[INFO] Available at {}", url))
+                        )?;
+                    }
+                    if let Ok(code) = var("QR_CODE") {
+                        writeln!(buf, "{}", code)?;
+                    }
+                    return Ok(());
                 }
                 if data == "SIGINT received; starting forced shutdown" {
                     return writeln!(
@@ -475,7 +2418,19 @@ async fn main() -> io::Result<()> {
             {
                 return Ok(());
             }
+            if is_disconnect(&data) {
+                // Clients cancelling downloads is routine, not worth an error
+                if log::max_level() < log::LevelFilter::Debug {
+                    return Ok(());
+                }
+                return writeln!(
+                    buf,
+                    "\r{}",
+                    green.value(format!("[DEBUG] Transfer aborted by the client: {}", data))
+                );
+            }
             if data.starts_with("[ERROR]")
+                || data.starts_with("[WARN]")
                 || data.starts_with("TLS alert")
                 || data.starts_with("Failed")
             {
@@ -485,54 +2440,123 @@ async fn main() -> io::Result<()> {
             }
         })
         .init();
+    if let Some(path) = matches.get_one::<String>("template") {
+        load_template(Path::new(path));
+    }
+    if let Some(e) = log_file_error {
+        error!("[ERROR] Failed to open log file: {}", e);
+        return Ok(());
+    }
+
+    if var("SINGLE_FILE").is_ok() && var("OVERLAY").is_ok() {
+        error!("[ERROR] --overlay needs a root directory, not a single file");
+        return Ok(());
+    }
+
+    if var("SINGLE_FILE").is_err() && matches.subcommand_matches("doc").is_none() {
+        let root = PathBuf::from(var("ROOT").unwrap_or_else(|_| ".".to_string()));
+        if sensitive::is_sensitive_root(&root) && !matches.get_flag("force") {
+            error!(
+                "[ERROR] Refusing to serve {}, it is a home directory or a filesystem root, serve a subdirectory or pass --force",
+                root.display()
+            );
+            return Ok(());
+        }
+        // Only what is actually reachable, hidden dotfiles are not worth a warning
+        let dotfiles = var("DOTFILES").unwrap_or_else(|_| "false".to_string()) == "true";
+        let exposed = sensitive::entries(&root)
+            .into_iter()
+            .filter(|name| {
+                dotfiles || !name.starts_with('.') || matches_globs("SHOW_DOTFILE", Path::new(name))
+            })
+            .filter(|name| !is_excluded(Path::new(name)))
+            .collect::<Vec<_>>();
+        if !exposed.is_empty() {
+            warn!(
+                "[WARN] {} contains {}, which may hold secrets, anyone who can reach this server can download them; hide them with --exclude or serve another directory",
+                root.display(),
+                exposed.join(", ")
+            );
+        }
+    }
+
+    if let Some(mut cors) = matches.get_many::<String>("cors") {
+        set_var("ENABLE_CORS", "true");
+        let origin = cors.next().map(String::as_str).unwrap_or("*");
+        if origin != "*" && origin.parse::<http::Uri>().is_err() {
+            error!("[ERROR] Invalid CORS origin: {}", origin);
+            return Ok(());
+        }
+        set_var("CORS", origin);
+        if matches.get_flag("cors-credentials") {
+            // Browsers reject `Access-Control-Allow-Origin: *` on credentialed requests
+            if origin == "*" {
+                error!("[ERROR] --cors-credentials requires a specific origin, e.g. --cors https://example.com");
+                return Ok(());
+            }
+            set_var("CORS_CREDENTIALS", "true");
+        }
+    }
 
     let addr = if let Some(matches) = matches.subcommand_matches("doc") {
-        let mut cargo_toml = match fs::File::open("./Cargo.toml") {
-            Ok(file) => file,
+        let metadata = match cargo_metadata() {
+            Ok(metadata) => metadata,
             Err(e) => {
-                error!("[ERROR] {}", e.to_string());
+                error!("[ERROR] {}", e);
                 return Ok(());
             }
         };
-        let mut contents = String::new();
-        match cargo_toml.read_to_string(&mut contents) {
-            Ok(_) => {}
+        let package = match doc_package(&metadata, matches.get_one::<String>("package")) {
+            Ok(package) => package,
             Err(e) => {
-                error!("[ERROR] {}", e.to_string());
+                error!("[ERROR] {}", e);
                 return Ok(());
             }
-        }
-        let contents: CargoToml = match toml::from_str(&contents) {
-            Ok(t) => t,
-            Err(e) => {
-                error!("[ERROR] {}", e.to_string());
+        };
+        set_var("DOC_PACKAGE", &package.name);
+        let crate_name = doc_dir_name(package);
+        if matches.get_flag("watch") {
+            // Next to the docs rather than in a shared temp dir others can plant links in
+            let header = metadata.target_directory.join("srv-livereload.html");
+            if let Err(e) = fs::create_dir_all(&metadata.target_directory)
+                .and_then(|_| fs::write(&header, watch::LIVERELOAD_SCRIPT))
+            {
+                error!("[ERROR] {}", e);
                 return Ok(());
             }
-        };
-        let crate_name = contents.package.name;
+            // The encoded form survives spaces in the target dir path
+            let mut flags = match var("CARGO_ENCODED_RUSTDOCFLAGS") {
+                Ok(flags) => flags.split('\x1f').map(str::to_string).collect(),
+                Err(_) => var("RUSTDOCFLAGS")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+            };
+            flags.retain(|flag| !flag.is_empty());
+            flags.push("--html-in-header".to_string());
+            flags.push(header.display().to_string());
+            set_var("CARGO_ENCODED_RUSTDOCFLAGS", flags.join("\x1f"));
+        }
         info!("[INFO] Generating document (may take a while)");
-        match Command::new("cargo")
-            .arg("doc")
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
-        {
-            Ok(status) => {
-                if !status.success() {
-                    match status.code() {
-                        Some(code) => error!("[ERROR] Cargo exited with status code: {code}"),
-                        None => error!("[ERROR] Cargo terminated by signal"),
-                    }
-                    return Ok(());
-                }
-            }
-            Err(e) => {
-                error!("[ERROR] Cargo Error: {}", e.to_string());
+        if !generate_doc() {
+            return Ok(());
+        }
+        if matches.get_flag("watch") {
+            let dir = package
+                .manifest_path
+                .parent()
+                .unwrap_or_else(|| Path::new("."));
+            let paths = [dir.join("src"), package.manifest_path.clone()];
+            let paths = paths.iter().filter(|path| path.exists()).cloned();
+            if let Err(e) = watch::spawn(&paths.collect::<Vec<_>>(), generate_doc) {
+                error!("[ERROR] Watch error: {}", e);
                 return Ok(());
             }
+            set_var("WATCH", "true");
         }
-        let path = Path::new("./target/doc/");
+        let path = metadata.target_directory.join("doc");
+        let path = path.as_path();
         let mut index_path = path.to_path_buf();
         index_path.push(crate_name.to_string() + "/index.html");
         if !index_path.exists() || !index_path.is_file() {
@@ -560,111 +2584,133 @@ async fn main() -> io::Result<()> {
             crate_name,
         );
         if !matches.get_flag("noopen") {
-            open_in_browser(&url);
+            set_var("OPEN_URL", &url);
         }
         addr
     } else {
         addr
     };
     set_var("LISTEN_ADDRESS", addr);
+    if matches.get_flag("verbose") {
+        log_config(enable_tls);
+    }
 
-    let server = HttpServer::new(move || {
-        let app = App::new()
-            .wrap_fn(|req, srv| {
-                let paths = PathBuf::from_str(req.path()).unwrap_or_default();
-                let mut isdotfile = false;
-                for path in paths.iter() {
-                    if path.to_string_lossy().starts_with('.') {
-                        isdotfile = true;
-                    }
-                }
-                let fut = srv.call(req);
-                async move {
-                    Ok(fut.await?.map_body(|head, body| {
-                        if var("NOCACHE").unwrap_or_else(|_| "false".to_string()) == "true" {
-                            head.headers_mut().insert(
-                                http::header::CACHE_CONTROL,
-                                http::header::HeaderValue::from_static("no-store"),
-                            );
-                        }
-                        if var("ENABLE_CORS").unwrap_or_else(|_| "false".to_string()) == "true" {
-                            let cors = var("CORS").unwrap_or_else(|_| "*".to_string());
-                            let cors = http::header::HeaderValue::from_str(&cors)
-                                .unwrap_or_else(|_| http::header::HeaderValue::from_static("*"));
-                            head.headers_mut()
-                                .insert(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, cors);
-                        }
-                        if isdotfile
-                            && var("DOTFILES").unwrap_or_else(|_| "false".to_string()) != "true"
-                        {
-                            return Response::new(http::StatusCode::FORBIDDEN).into_body();
-                        }
-                        body
-                    }))
-                }
-            })
-            .wrap(middleware::Compress::default())
-            .wrap(middleware::Condition::new(
-                var("ENABLE_AUTH").unwrap_or_else(|_| "false".to_string()) == "true",
-                HttpAuthentication::basic(validator),
-            ))
-            .wrap(middleware::Logger::new("%t^%a^%s^%D^%r"));
-        let files = actix_files::Files::new("/", var("ROOT").unwrap_or_else(|_| ".".to_string()))
-            .use_hidden_files()
-            .prefer_utf8(true)
-            .show_files_listing()
-            .files_listing_renderer(render_index)
-            .default_handler(|req: ServiceRequest| {
-                let (http_req, _payload) = req.into_parts();
-                async {
-                    let path = var("ROOT").unwrap_or_else(|_| ".".to_string());
-                    let mut path = Path::new(&path).to_path_buf();
-                    path.push("index.html");
-                    if path.exists()
-                        && path.is_file()
-                        && var("SPA").unwrap_or_else(|_| "false".to_string()) == "true"
-                    {
-                        let res = actix_files::NamedFile::open(path)?.into_response(&http_req);
-                        return Ok(ServiceResponse::new(http_req, res));
-                    }
-                    Ok(ServiceResponse::new(
-                        http_req,
-                        HttpResponse::NotFound().body(""),
-                    ))
-                }
-            });
-        app.service(files)
-    });
-    let server = if enable_tls {
-        let cert = &mut BufReader::new(
-            fs::File::open(Path::new(matches.get_one::<String>("cert").unwrap())).unwrap(),
-        );
-        let key = &mut BufReader::new(
-            fs::File::open(Path::new(matches.get_one::<String>("key").unwrap())).unwrap(),
-        );
-        let cert = rustls_pemfile::certs(cert)
-            .unwrap()
-            .iter()
-            .map(|x| rustls::Certificate(x.to_vec()))
-            .collect::<Vec<_>>();
-        let key = rustls::PrivateKey(
-            rustls_pemfile::pkcs8_private_keys(key)
-                .unwrap()
-                .first()
-                .expect("no private key found")
-                .to_owned(),
-        );
-        let config = rustls::ServerConfig::builder()
+    let server = HttpServer::new(app);
+    let server = match var("WORKERS").ok().and_then(|n| n.parse().ok()) {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    let seconds = |key: &str| {
+        var(key)
+            .ok()
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(5)
+    };
+    let server = server
+        // Zero disables both, actix maps it that way itself
+        .keep_alive(std::time::Duration::from_secs(seconds("KEEP_ALIVE")))
+        .client_request_timeout(std::time::Duration::from_secs(seconds("CLIENT_TIMEOUT")));
+    let tls_config = if enable_tls {
+        let cert = match fs::File::open(Path::new(matches.get_one::<String>("cert").unwrap())) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("[ERROR] Failed to open certificate: {}", e);
+                return Ok(());
+            }
+        };
+        let cert = match rustls_pemfile::certs(&mut BufReader::new(cert)) {
+            Ok(cert) => cert
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("[ERROR] Failed to read certificate: {}", e);
+                return Ok(());
+            }
+        };
+        if let Err(e) = check_cert_chain(&cert) {
+            error!("[ERROR] {}", e);
+            return Ok(());
+        }
+        let key = match load_private_key(Path::new(matches.get_one::<String>("key").unwrap())) {
+            Ok(Some(key)) => key,
+            Ok(None) => {
+                error!("[ERROR] No PKCS#8, RSA or EC private key found");
+                return Ok(());
+            }
+            Err(e) => {
+                error!("[ERROR] Failed to read private key: {}", e);
+                return Ok(());
+            }
+        };
+        // ALPN is left empty on purpose: `bind_rustls` always advertises "h2" and
+        // "http/1.1" itself, so capable clients negotiate HTTP/2 and everyone else
+        // falls back to HTTP/1.1. Anything set here is appended after those two.
+        match rustls::ServerConfig::builder()
             .with_safe_defaults()
             .with_no_client_auth()
             .with_single_cert(cert, key)
-            .expect("bad certificate/key");
-        server.bind_rustls(
-            var("LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8000".to_string()),
-            config,
-        )
+        {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!("[ERROR] Bad certificate/key: {}", e);
+                return Ok(());
+            }
+        }
     } else {
-        server.bind(var("LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8000".to_string()))
+        None
     };
-    server?.run().await
+    let addrs = var("LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8000".to_string());
+    // The redirect listens on the same addresses as HTTPS, just on its own port
+    let mut redirects = var("HTTPS_REDIRECT")
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .map(|port| {
+            addrs
+                .lines()
+                .filter_map(|addr| addr.parse::<std::net::SocketAddr>().ok())
+                .map(|addr| std::net::SocketAddr::new(addr.ip(), port).to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    redirects.dedup();
+    let binds = addrs
+        .lines()
+        .map(|addr| (addr.to_string(), tls_config.clone()))
+        .chain(redirects.into_iter().map(|addr| (addr, None)));
+    let mut server = server;
+    for (addr, config) in binds {
+        let bound = match config {
+            Some(config) => server.bind_rustls(&addr, config),
+            None => server.bind(&addr),
+        };
+        server = match bound {
+            Ok(server) => server,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::AddrNotAvailable {
+                    let addresses = interfaces::addresses()
+                        .iter()
+                        .map(|addr| addr.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    error!(
+                        "[ERROR] Cannot listen on {}: the address is not assigned to this machine, use 0.0.0.0 to listen on all interfaces or one of: {}",
+                        addr, addresses
+                    );
+                } else {
+                    error!("[ERROR] Cannot listen on {}: {}", addr, e);
+                }
+                return Ok(());
+            }
+        };
+    }
+    if let Ok(url) = var("OPEN_URL") {
+        let addr = addrs.lines().next().unwrap_or_default().to_string();
+        std::thread::spawn(move || {
+            if wait_until_listening(&addr) {
+                open_in_browser(&url);
+            }
+        });
+    }
+    server.run().await
 }
@@ -0,0 +1,146 @@
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    http,
+    web::Bytes,
+    HttpResponse,
+};
+use std::{
+    fmt::Write,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Upper bounds of the request duration histogram, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+/// Responses by status class, `1xx` to `5xx`
+static RESPONSES: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static DURATION_BUCKETS: [AtomicU64; 11] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+/// Sum of all request durations, in microseconds
+static DURATION_SUM: AtomicU64 = AtomicU64::new(0);
+
+/// Count a finished request. `duration` is the time until the response head
+/// was ready, the same as the `%D` of the access log.
+pub fn record(status: http::StatusCode, duration: Duration) {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    if let Some(class) = RESPONSES.get((status.as_u16() / 100) as usize - 1) {
+        class.fetch_add(1, Ordering::Relaxed);
+    }
+    let seconds = duration.as_secs_f64();
+    for (bucket, count) in BUCKETS.iter().zip(DURATION_BUCKETS.iter()) {
+        if seconds <= *bucket {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    DURATION_SUM.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// A response body that adds what it sends to the bytes served counter.
+pub struct Counted(pub BoxBody);
+
+impl MessageBody for Counted {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.0.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.0).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            BYTES.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+pub async fn metrics() -> HttpResponse {
+    let mut out = String::new();
+    let requests = REQUESTS.load(Ordering::Relaxed);
+    let _ = writeln!(
+        out,
+        "# HELP srv_requests_total Total number of HTTP requests."
+    );
+    let _ = writeln!(out, "# TYPE srv_requests_total counter");
+    let _ = writeln!(out, "srv_requests_total {}", requests);
+    let _ = writeln!(
+        out,
+        "# HELP srv_responses_total HTTP responses by status class."
+    );
+    let _ = writeln!(out, "# TYPE srv_responses_total counter");
+    for (i, count) in RESPONSES.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "srv_responses_total{{class=\"{}xx\"}} {}",
+            i + 1,
+            count.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP srv_bytes_served_total Response body bytes sent."
+    );
+    let _ = writeln!(out, "# TYPE srv_bytes_served_total counter");
+    let _ = writeln!(
+        out,
+        "srv_bytes_served_total {}",
+        BYTES.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "# HELP srv_request_duration_seconds Time until the response head was ready."
+    );
+    let _ = writeln!(out, "# TYPE srv_request_duration_seconds histogram");
+    for (bucket, count) in BUCKETS.iter().zip(DURATION_BUCKETS.iter()) {
+        let _ = writeln!(
+            out,
+            "srv_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+            bucket,
+            count.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "srv_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+        requests
+    );
+    let _ = writeln!(
+        out,
+        "srv_request_duration_seconds_sum {}",
+        DURATION_SUM.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    );
+    let _ = writeln!(out, "srv_request_duration_seconds_count {}", requests);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .insert_header((http::header::CACHE_CONTROL, "no-store"))
+        .body(out)
+}
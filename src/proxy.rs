@@ -0,0 +1,132 @@
+use crate::realip::Peer;
+use actix_web::{http::header, web, HttpMessage, HttpRequest, HttpResponse, Scope};
+use log::error;
+use std::{env::var, time::Duration};
+
+/// How long the upstream has to start answering before the client gets a 502
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that only describe a single connection and must not be forwarded
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Everything under `prefix` is sent to `upstream` with its full path and
+/// query, e.g. `/api/users?page=2` to `http://localhost:3000/api/users?page=2`.
+pub fn scope(prefix: &str, upstream: &str) -> Scope {
+    let upstream = upstream.trim_end_matches('/').to_string();
+    web::scope(prefix).default_service(web::to(
+        move |req: HttpRequest, payload: web::Payload, client: web::Data<awc::Client>| {
+            forward(req, payload, client, upstream.clone())
+        },
+    ))
+}
+
+/// Whether `path` is answered by one of the `PROXY` scopes, whose responses
+/// are passed on as the upstream sent them.
+pub fn is_proxied(path: &str) -> bool {
+    var("PROXY")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|proxy| proxy.split_once('='))
+        .any(|(prefix, _)| {
+            let prefix = prefix.trim_end_matches('/');
+            path.strip_prefix(prefix)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+        })
+}
+
+fn is_hop_by_hop(name: &header::HeaderName, connection: &[String]) -> bool {
+    HOP_BY_HOP.contains(&name.as_str()) || connection.iter().any(|h| h == name.as_str())
+}
+
+/// Names listed in `Connection`, which are hop-by-hop as well
+fn connection_headers(headers: &header::HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::CONNECTION)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect()
+}
+
+async fn forward(
+    req: HttpRequest,
+    payload: web::Payload,
+    client: web::Data<awc::Client>,
+    upstream: String,
+) -> HttpResponse {
+    let url = format!(
+        "{}{}",
+        upstream,
+        req.uri().path_and_query().map_or("/", |path| path.as_str())
+    );
+    let mut forwarded = client
+        .request(req.method().clone(), url)
+        .no_decompress()
+        .timeout(TIMEOUT);
+    let connection = connection_headers(req.headers());
+    for (name, value) in req.headers() {
+        // awc sets Host from the upstream URL
+        if name != header::HOST && !is_hop_by_hop(name, &connection) {
+            forwarded = forwarded.append_header((name.clone(), value.clone()));
+        }
+    }
+    let info = req.connection_info().clone();
+    // The hop is the proxy in front of us when realip put its client in peer_addr
+    let peer = req.extensions().get::<Peer>().map(|peer| peer.0);
+    if let Some(peer) = peer.or_else(|| req.peer_addr()) {
+        let ip = peer.ip();
+        let forwarded_for = match req.headers().get("x-forwarded-for") {
+            Some(value) => format!("{}, {}", value.to_str().unwrap_or_default(), ip),
+            None => ip.to_string(),
+        };
+        forwarded = forwarded.insert_header(("x-forwarded-for", forwarded_for));
+    }
+    forwarded = forwarded.insert_header(("x-forwarded-proto", info.scheme()));
+    // A request without a body goes out without one, not as an empty chunked stream
+    let has_body = req.headers().contains_key(header::CONTENT_LENGTH)
+        || req.headers().contains_key(header::TRANSFER_ENCODING);
+    let sent = if has_body {
+        forwarded.send_stream(payload).await
+    } else {
+        forwarded.send().await
+    };
+    let res = match sent {
+        Ok(res) => res,
+        Err(e) => {
+            error!("[ERROR] Proxy to {} failed: {}", upstream, e);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+    let mut builder = HttpResponse::build(res.status());
+    let connection = connection_headers(res.headers());
+    for (name, value) in res.headers() {
+        if !is_hop_by_hop(name, &connection) {
+            builder.append_header((name.clone(), value.clone()));
+        }
+    }
+    builder.streaming(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Fixture;
+
+    #[test]
+    fn proxied_paths() {
+        let _fixture = Fixture::new(&[("PROXY", "/api=http://localhost:3000")]);
+        assert!(is_proxied("/api"));
+        assert!(is_proxied("/api/users"));
+        assert!(!is_proxied("/apidocs"));
+        assert!(!is_proxied("/"));
+    }
+}
@@ -0,0 +1,125 @@
+use actix_web::http::header::HeaderMap;
+use std::{
+    env::var,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Proxies trusted by a bare `--behind-proxy`, a reverse proxy on this machine
+pub const DEFAULT_TRUSTED: &str = "127.0.0.0/8,::1/128";
+
+/// The proxy a request came in from, kept in the request extensions once its
+/// address has been replaced with the client's
+#[derive(Clone, Copy)]
+pub struct Peer(pub SocketAddr);
+
+/// Parse `ip` or `ip/prefix-length` into an address and a prefix length.
+pub fn parse_cidr(s: &str) -> Result<(IpAddr, u8), String> {
+    let (ip, len) = s.split_once('/').unwrap_or((s, ""));
+    let ip = ip.trim().parse::<IpAddr>().map_err(|e| e.to_string())?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let len = match len.trim() {
+        "" => max,
+        len => len.parse::<u8>().map_err(|e| e.to_string())?,
+    };
+    if len > max {
+        return Err(format!("Prefix length {} is too long for {}", len, ip));
+    }
+    Ok((ip, len))
+}
+
+fn in_cidr(ip: IpAddr, (net, len): (IpAddr, u8)) -> bool {
+    // Dual-stack sockets report IPv4 peers as `::ffff:a.b.c.d`
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    };
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Whether `ip` is one of the proxies in the comma separated `TRUSTED_PROXIES`.
+fn is_trusted(ip: IpAddr) -> bool {
+    var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|cidr| parse_cidr(cidr).ok())
+        .any(|cidr| in_cidr(ip, cidr))
+}
+
+/// The address of the client behind `peer`, if `peer` is a trusted proxy that
+/// says who it forwarded for. `X-Forwarded-For` is read from the right, every
+/// hop that is a trusted proxy itself is skipped, so a client cannot spoof its
+/// address by sending the header along. Entries that are not addresses are
+/// skipped as well, and when every hop is trusted, the leftmost one is the
+/// client. `X-Real-IP` is only read when there is no `X-Forwarded-For` at all,
+/// as a client can send that one too.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap) -> Option<IpAddr> {
+    if !is_trusted(peer) {
+        return None;
+    }
+    if headers.contains_key("x-forwarded-for") {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        let client = forwarded.iter().rev().find(|ip| !is_trusted(**ip));
+        return client.or_else(|| forwarded.first()).copied();
+    }
+    headers
+        .get("x-real-ip")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Fixture;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn client(headers: &[(&'static str, &'static str)]) -> Option<IpAddr> {
+        let mut map = HeaderMap::new();
+        for &(name, value) in headers {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        client_ip("127.0.0.1".parse().unwrap(), &map)
+    }
+
+    #[test]
+    fn forwarded_for_cannot_be_spoofed() {
+        let _fixture = Fixture::new(&[("TRUSTED_PROXIES", DEFAULT_TRUSTED)]);
+        let client_addr = Some("203.0.113.7".parse().unwrap());
+        // The client's own entries are left of the one the proxy appended
+        let spoofed = [("x-forwarded-for", "10.0.0.1, 203.0.113.7")];
+        assert_eq!(client(&spoofed), client_addr);
+        // Garbage does not make the header fall back to `X-Real-IP`
+        let garbage = [
+            ("x-forwarded-for", "nonsense, 203.0.113.7"),
+            ("x-real-ip", "10.0.0.1"),
+        ];
+        assert_eq!(client(&garbage), client_addr);
+        let unparsable = [("x-forwarded-for", "nonsense"), ("x-real-ip", "10.0.0.1")];
+        assert_eq!(client(&unparsable), None);
+        let trusted = [("x-forwarded-for", "127.0.0.2, 127.0.0.3")];
+        assert_eq!(client(&trusted), Some("127.0.0.2".parse().unwrap()));
+        assert_eq!(client(&[("x-real-ip", "203.0.113.7")]), client_addr);
+        assert_eq!(
+            client_ip("192.0.2.1".parse().unwrap(), &HeaderMap::new()),
+            None
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    env::var_os,
+    path::{Path, PathBuf},
+};
+
+/// Names that usually hold credentials or keys, looked for directly in the root
+const NAMES: [&str; 18] = [
+    ".ssh",
+    ".aws",
+    ".azure",
+    ".gnupg",
+    ".kube",
+    ".docker",
+    ".env",
+    ".netrc",
+    ".npmrc",
+    ".pypirc",
+    ".git-credentials",
+    ".bash_history",
+    ".zsh_history",
+    "id_rsa",
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    "credentials.json",
+];
+
+fn home_dir() -> Option<PathBuf> {
+    let home = var_os("HOME").or_else(|| var_os("USERPROFILE"))?;
+    Path::new(&home).canonicalize().ok()
+}
+
+/// Whether `root` (canonical) is a filesystem root or the home directory,
+/// which nobody means to publish as a whole.
+pub fn is_sensitive_root(root: &Path) -> bool {
+    root.parent().is_none() || home_dir().is_some_and(|home| home == root)
+}
+
+/// The entries of `root` that look like secrets, in the order of [`NAMES`].
+pub fn entries(root: &Path) -> Vec<&'static str> {
+    NAMES
+        .iter()
+        .copied()
+        .filter(|name| root.join(name).symlink_metadata().is_ok())
+        .collect()
+}
@@ -0,0 +1,73 @@
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    rt::time::{sleep_until, Instant, Sleep},
+    web::Bytes,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// A response body that is released no faster than `rate` bytes per second.
+/// Waiting is done with a timer, so the worker keeps serving other requests.
+pub struct Throttled {
+    body: BoxBody,
+    rate: u64,
+    started: Instant,
+    sent: u64,
+    /// Part of the last chunk that is not due yet
+    pending: Bytes,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttled {
+    pub fn new(body: BoxBody, rate: u64) -> Self {
+        Throttled {
+            body,
+            rate,
+            started: Instant::now(),
+            sent: 0,
+            pending: Bytes::new(),
+            delay: None,
+        }
+    }
+}
+
+impl MessageBody for Throttled {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(delay) = this.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+        }
+        if this.pending.is_empty() {
+            match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.pending = chunk,
+                other => return other,
+            }
+        }
+        // Hand out at most a tenth of a second worth of data at a time, so a
+        // large chunk from the file reader does not go out as one burst
+        let slice = (this.rate / 10).max(1) as usize;
+        let chunk = this.pending.split_to(slice.min(this.pending.len()));
+        this.sent += chunk.len() as u64;
+        let due = this.started + Duration::from_secs_f64(this.sent as f64 / this.rate as f64);
+        if due > Instant::now() {
+            this.delay = Some(Box::pin(sleep_until(due)));
+        }
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
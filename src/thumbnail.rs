@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Edges thumbnails are made at, a request is rounded up to the next one
+const SIZES: [u32; 4] = [64, 128, 200, 400];
+/// Sources with a larger edge are not decoded
+const MAX_SOURCE_DIMENSION: u32 = 10_000;
+/// Most memory the decoder may allocate for one source image
+const MAX_ALLOC: u64 = 256 * 1024 * 1024;
+/// Images bigger than this are not decoded, the listing keeps the generic icon
+pub const MAX_FILE_SIZE: u64 = 20 * 1024 * 1024;
+/// Upper bound on cached thumbnails, the cache is cleared when it is reached
+const MAX_CACHED: usize = 1024;
+
+type Key = (PathBuf, u32);
+
+struct Thumbnail {
+    modified: SystemTime,
+    content_type: &'static str,
+    data: Vec<u8>,
+}
+
+static CACHE: Mutex<Option<HashMap<Key, Thumbnail>>> = Mutex::new(None);
+
+/// Whether `path` looks like an image this build can decode.
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .is_some_and(|ext| {
+            matches!(
+                ext.as_str(),
+                "bmp" | "gif" | "jpeg" | "jpg" | "png" | "webp"
+            )
+        })
+}
+
+/// The smallest of `SIZES` that fits `size`, or the largest one
+fn rounded(size: u32) -> u32 {
+    SIZES
+        .iter()
+        .copied()
+        .find(|&edge| edge >= size)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// A thumbnail of `path` no larger than `size`, rounded up to one of `SIZES`,
+/// on either edge, along with its content type. Results are cached until the
+/// source file changes.
+pub fn get(path: &Path, size: u32) -> Option<(&'static str, Vec<u8>)> {
+    let metadata = path.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_FILE_SIZE || !is_supported(path) {
+        return None;
+    }
+    let modified = metadata.modified().ok()?;
+    let size = rounded(size);
+    let key = (path.to_path_buf(), size);
+    if let Some(cache) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if let Some(thumbnail) = cache.get(&key).filter(|t| t.modified == modified) {
+            return Some((thumbnail.content_type, thumbnail.data.clone()));
+        }
+    }
+    let mut reader = image::io::Reader::open(path).ok()?;
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+    reader.limits(limits);
+    let image = reader.decode().ok()?.thumbnail(size, size);
+    let mut data = Cursor::new(vec![]);
+    // Keep transparency where the source has it, JPEG is much smaller otherwise
+    let (image, format, content_type) = if image.color().has_alpha() {
+        (image, image::ImageOutputFormat::Png, "image/png")
+    } else {
+        let image = image::DynamicImage::ImageRgb8(image.to_rgb8());
+        (image, image::ImageOutputFormat::Jpeg(80), "image/jpeg")
+    };
+    image.write_to(&mut data, format).ok()?;
+    let data = data.into_inner();
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if cache.len() >= MAX_CACHED {
+        cache.clear();
+    }
+    cache.insert(
+        key,
+        Thumbnail {
+            modified,
+            content_type,
+            data: data.clone(),
+        },
+    );
+    Some((content_type, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_rounded_up() {
+        assert_eq!(rounded(0), 64);
+        assert_eq!(rounded(48), 64);
+        assert_eq!(rounded(64), 64);
+        assert_eq!(rounded(65), 128);
+        assert_eq!(rounded(199), 200);
+        assert_eq!(rounded(4000), 400);
+    }
+}
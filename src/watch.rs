@@ -0,0 +1,73 @@
+use actix_web::{http, HttpResponse};
+use log::{error, info};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Bumped after every successful rebuild, polled by the injected script.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub const LIVERELOAD_PATH: &str = "/__srv/livereload";
+
+pub const LIVERELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var generation = null;
+  setInterval(function () {
+    fetch("/__srv/livereload", { cache: "no-store" })
+      .then(function (res) { return res.text(); })
+      .then(function (text) {
+        if (generation !== null && generation !== text) location.reload();
+        generation = text;
+      })
+      .catch(function () {});
+  }, 1000);
+})();
+</script>
+"#;
+
+pub async fn livereload() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((http::header::CACHE_CONTROL, "no-store"))
+        .body(GENERATION.load(Ordering::SeqCst).to_string())
+}
+
+/// Watch `paths` and call `rebuild` once per burst of changes.
+pub fn spawn<F>(paths: &[PathBuf], rebuild: F) -> notify::Result<()>
+where
+    F: Fn() -> bool + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for path in paths {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+    thread::spawn(move || {
+        let _watcher = watcher;
+        while let Ok(event) = rx.recv() {
+            match event {
+                Ok(event) => match event.kind {
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+                    _ => continue,
+                },
+                Err(e) => {
+                    error!("[ERROR] Watch error: {}", e);
+                    continue;
+                }
+            }
+            // Editors tend to emit several events per save
+            while rx.recv_timeout(Duration::from_millis(300)).is_ok() {}
+            info!("[INFO] Source changed, regenerating document");
+            if rebuild() {
+                GENERATION.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    Ok(())
+}